@@ -0,0 +1,199 @@
+use std::path::PathBuf;
+
+use distrox_types::id::NodeId;
+
+use crate::output::Format;
+
+#[derive(Debug, clap::Parser)]
+#[command(author, version, about)]
+pub struct Cli {
+    /// Do not listen on any address or connect to any peer
+    ///
+    /// Posts are stored locally and published once distrox runs online again.
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Use the XDG directories of this name instead of those of `distrox`
+    ///
+    /// Instances with different prefixes have their own configuration, state, keys and control
+    /// socket, so they can run side by side.
+    #[arg(
+        long,
+        global = true,
+        env = "DISTROX_XDG_PREFIX",
+        default_value = "distrox"
+    )]
+    pub xdg_prefix: String,
+
+    /// Use this profile, each profile has its own identity, configuration and blockstore
+    ///
+    /// A profile is created the first time it is used.
+    #[arg(long, global = true, env = "DISTROX_PROFILE")]
+    pub profile: Option<String>,
+
+    /// The control socket of the daemon, defaults to `control.sock` in the XDG runtime directory
+    ///
+    /// Commands are sent to the daemon if it runs, otherwise they are handled directly.
+    #[arg(long, global = true)]
+    pub socket: Option<PathBuf>,
+
+    /// How to print results
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// Print results as JSON, short for `--format json`
+    #[arg(long, global = true, conflicts_with = "format")]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub network: NetworkArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Settings that take precedence over `[network]` of the configuration file
+#[derive(Debug, clap::Args)]
+#[command(next_help_heading = "Network")]
+pub struct NetworkArgs {
+    /// Keep the blockstore here
+    #[arg(long, global = true, env = "DISTROX_STORAGE_PATH")]
+    pub storage_path: Option<PathBuf>,
+
+    /// Listen on this multiaddr, can be given multiple times
+    ///
+    /// The environment variable takes a comma separated list.
+    #[arg(
+        long = "listening-addr",
+        global = true,
+        env = "DISTROX_LISTENING_ADDRS",
+        value_delimiter = ','
+    )]
+    pub listening_addrs: Vec<String>,
+
+    /// Bootstrap from this multiaddr, can be given multiple times
+    ///
+    /// The environment variable takes a comma separated list.
+    #[arg(
+        long = "bootstrap-node",
+        global = true,
+        env = "DISTROX_BOOTSTRAP_NODES",
+        value_delimiter = ','
+    )]
+    pub bootstrap_nodes: Vec<String>,
+
+    /// Maximum size of the blockstore in bytes
+    #[arg(long, global = true, env = "DISTROX_QUOTA_BYTES")]
+    pub quota_bytes: Option<u64>,
+}
+
+impl From<NetworkArgs> for distrox_lib::configuration::NetworkOverrides {
+    fn from(args: NetworkArgs) -> Self {
+        let non_empty = |addrs: Vec<String>| Some(addrs).filter(|addrs| !addrs.is_empty());
+
+        distrox_lib::configuration::NetworkOverrides {
+            storage_path: args.storage_path,
+            bootstrap_nodes: non_empty(args.bootstrap_nodes),
+            listening_addrs: non_empty(args.listening_addrs),
+            quota_bytes: args.quota_bytes,
+        }
+    }
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Start the graphical interface (default)
+    Gui,
+
+    /// Start the terminal interface
+    ///
+    /// While it runs, logs go to tui.log in the state directory.
+    Tui,
+
+    /// Run without interface, controlled through a local socket
+    Daemon,
+
+    /// Create the configuration, state and device key, if they do not exist yet
+    ///
+    /// All other commands do this as well when they start.
+    Init,
+
+    /// List the profiles that are set up
+    Profiles,
+
+    /// Inspect or reload the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Publish a text post
+    Post {
+        /// The text, read from stdin if neither it nor a file is given, or if it is "-"
+        #[arg(conflicts_with = "file")]
+        text: Option<String>,
+
+        /// Read the text from a file
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Print our own timeline merged with those of the authors we follow
+    Timeline {
+        /// How many posts to print at most
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+
+    /// Follow the timeline of an account
+    Follow {
+        /// The account id of the author
+        author: String,
+    },
+
+    /// Stop following the timeline of an account
+    Unfollow {
+        /// The account id of the author
+        author: String,
+    },
+
+    /// Connect to a peer
+    Connect {
+        /// The multiaddr of the peer
+        multiaddr: String,
+    },
+
+    /// List the peers we are connected to
+    Peers,
+
+    /// Print the device id and the account of this device
+    Whoami,
+
+    /// Export a timeline with all its posts and content into a CAR file
+    ExportCar {
+        /// The node to start from, defaults to the head of our own timeline
+        #[arg(long)]
+        head: Option<NodeId>,
+
+        /// The file to write to
+        path: PathBuf,
+    },
+
+    /// Import all blocks of a CAR file into the blockstore
+    ImportCar {
+        /// The file to read from
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ConfigCommand {
+    /// Check the configuration for mistakes, fails if there are any
+    Check {
+        /// The file to check, defaults to `config.toml` in the XDG config directory
+        path: Option<PathBuf>,
+    },
+
+    /// Make the running daemon read its configuration again
+    Reload,
+}
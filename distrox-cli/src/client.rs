@@ -0,0 +1,45 @@
+use std::path::Path;
+
+use distrox_lib::application::Application;
+use distrox_lib::command::Command;
+use distrox_lib::command::CommandOutput;
+use tracing::debug;
+
+use crate::error::Error;
+
+/// Let a running daemon handle `command`, or start the application just for this command
+///
+/// The blockstore can only be opened once, so the daemon has to be used if it runs.
+pub async fn request(
+    socket: &Path,
+    xdg: xdg::BaseDirectories,
+    options: distrox_lib::application::Options,
+    command: Command,
+) -> Result<CommandOutput, Error> {
+    match distrox_lib::rpc::Client::connect(socket).await {
+        Ok(mut client) => {
+            debug!(socket = %socket.display(), "Sending command to daemon");
+            client.request(command).await.map_err(Error::from)
+        }
+
+        Err(error) => {
+            debug!(%error, "No daemon running, handling command locally");
+            let app = Application::load_from_xdg(xdg, options).await?;
+            request_locally(&app, command).await
+        }
+    }
+}
+
+async fn request_locally(app: &Application, command: Command) -> Result<CommandOutput, Error> {
+    let (sender, receiver) = distrox_lib::command::channel(1);
+
+    let request = async {
+        let result = sender.request(command).await;
+        let _ = sender.send(Command::QuitApp).await;
+        result
+    };
+
+    let (result, run) = tokio::join!(request, app.run(receiver));
+    run?;
+    result.map_err(Error::from)
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use distrox_lib::application::Application;
+use distrox_lib::command::Command;
+use distrox_lib::command::CommandSender;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+use tracing::{info, warn};
+
+use crate::error::Error;
+
+/// Where the daemon listens for commands if no socket is given
+pub fn default_socket_path(xdg: &xdg::BaseDirectories) -> Result<PathBuf, Error> {
+    xdg.place_runtime_file("control.sock")
+        .or_else(|_| xdg.place_state_file("control.sock"))
+        .map_err(|source| Error::ControlSocket {
+            path: PathBuf::from("control.sock"),
+            source,
+        })
+}
+
+/// Run the application until it receives `Command::QuitApp`, SIGTERM or SIGINT
+///
+/// The control socket speaks JSON-RPC, see `distrox_lib::rpc`. SIGHUP reloads the configuration.
+pub async fn run(app: Application, socket_path: PathBuf) -> Result<(), Error> {
+    let listener = bind(&socket_path).await?;
+    info!(socket = %socket_path.display(), "Accepting commands");
+
+    let (sender, receiver) = distrox_lib::command::channel(100);
+    let control_task = tokio::spawn(distrox_lib::rpc::serve(
+        listener,
+        sender.clone(),
+        app.events(),
+    ));
+    let signal_task = tokio::spawn(handle_signals(sender));
+
+    let result = app.run(receiver).await;
+
+    control_task.abort();
+    signal_task.abort();
+    if let Err(error) = tokio::fs::remove_file(&socket_path).await {
+        warn!(?error, "Failed to remove control socket");
+    }
+
+    result.map_err(Error::from)
+}
+
+async fn bind(path: &Path) -> Result<UnixListener, Error> {
+    // A socket file nobody listens on is left over from a daemon that did not shut down cleanly
+    if tokio::fs::try_exists(path).await.unwrap_or(false) {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(Error::DaemonRunning(path.to_path_buf()));
+        }
+
+        tokio::fs::remove_file(path)
+            .await
+            .map_err(|source| Error::ControlSocket {
+                path: path.to_path_buf(),
+                source,
+            })?;
+    }
+
+    UnixListener::bind(path).map_err(|source| Error::ControlSocket {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+async fn handle_signals(sender: CommandSender) -> Result<(), Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).map_err(Error::Signal)?;
+    let mut hangup = signal(SignalKind::hangup()).map_err(Error::Signal)?;
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                info!("Received SIGHUP, reloading configuration");
+                let _ = sender.send(Command::ReloadConfig).await;
+            }
+            _ = terminate.recv() => {
+                info!("Received SIGTERM, shutting down");
+                break;
+            }
+            result = tokio::signal::ctrl_c() => {
+                result.map_err(Error::Signal)?;
+                info!("Received SIGINT, shutting down");
+                break;
+            }
+        }
+    }
+
+    let _ = sender.send(Command::QuitApp).await;
+    Ok(())
+}
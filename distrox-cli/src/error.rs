@@ -6,9 +6,51 @@ pub enum Error {
     #[error(transparent)]
     DistroxGui(#[from] distrox_gui::error::Error),
 
+    #[error(transparent)]
+    DistroxTui(#[from] distrox_tui::error::Error),
+
     #[error(transparent)]
     Xdg(#[from] xdg::BaseDirectoriesError),
 
     #[error(transparent)]
     Join(#[from] tokio::task::JoinError),
+
+    #[error("Cannot listen on control socket {}", .path.display())]
+    ControlSocket {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Another daemon is already listening on {}", .0.display())]
+    DaemonRunning(std::path::PathBuf),
+
+    #[error("Cannot listen for signals")]
+    Signal(#[source] std::io::Error),
+
+    #[error("Cannot open log file {}", .path.display())]
+    LogFile {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to serialize output")]
+    SerializingOutput(#[source] serde_json::Error),
+
+    #[error("Failed to write output")]
+    WritingOutput(#[source] std::io::Error),
+
+    #[error("Found {0} problems in the configuration")]
+    ConfigProblems(usize),
+
+    #[error("Cannot find the current directory")]
+    CurrentDir(#[source] std::io::Error),
+
+    #[error("Failed to read text from {path}")]
+    ReadingText {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
 }
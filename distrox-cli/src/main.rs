@@ -1,25 +1,341 @@
+mod cli;
+mod client;
+mod daemon;
 mod error;
+mod output;
 
 use crate::error::Error;
 
+use clap::Parser;
 use futures::FutureExt;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    tracing_subscriber::fmt::init();
-    let xdg = xdg::BaseDirectories::with_prefix("distrox")?;
+    let cli = crate::cli::Cli::parse();
+    let xdg = distrox_lib::profile::xdg_directories(&cli.xdg_prefix, cli.profile.as_deref())?;
+    init_logging(cli.command.as_ref(), &xdg)?;
 
-    let (sender, receiver) = tokio::sync::mpsc::channel(100);
+    let options = distrox_lib::application::Options {
+        offline: cli.offline,
+        network: cli.network.into(),
+    };
+    let format = if cli.json {
+        crate::output::Format::Json
+    } else {
+        cli.format
+    };
+    let socket = match cli.socket {
+        Some(socket) => socket,
+        None => crate::daemon::default_socket_path(&xdg)?,
+    };
 
-    let app = distrox_lib::application::Application::load_from_xdg(xdg).await?;
+    let command = match cli.command.unwrap_or(crate::cli::Command::Gui) {
+        crate::cli::Command::Gui => {
+            let app =
+                distrox_lib::application::Application::load_from_xdg(xdg, options.clone()).await?;
+            let profile = cli
+                .profile
+                .unwrap_or_else(|| distrox_lib::profile::DEFAULT_PROFILE.to_string());
+            return Ok(run_gui(app, options, cli.xdg_prefix, profile).await?);
+        }
 
-    let gui_task = tokio::task::spawn_blocking(|| distrox_gui::start(sender).map_err(Error::from))
-        .map(|r| match r {
-            Ok(res) => res,
-            Err(join) => Err(Error::Join(join)),
-        });
-    let app_task = app.run(receiver).map(|r| r.map_err(Error::from));
+        crate::cli::Command::Tui => {
+            let app = distrox_lib::application::Application::load_from_xdg(xdg, options).await?;
+            return Ok(run_tui(app).await?);
+        }
+
+        crate::cli::Command::Daemon => {
+            let app = distrox_lib::application::Application::load_from_xdg(xdg, options).await?;
+            return Ok(crate::daemon::run(app, socket).await?);
+        }
+
+        crate::cli::Command::Init => {
+            let setup = distrox_lib::application::Application::init_xdg(&xdg).await?;
+            return Ok(crate::output::print_setup(&setup, format)?);
+        }
+
+        crate::cli::Command::Profiles => {
+            let names = distrox_lib::profile::list(&cli.xdg_prefix)?;
+            let current = cli
+                .profile
+                .as_deref()
+                .unwrap_or(distrox_lib::profile::DEFAULT_PROFILE);
+            return Ok(crate::output::print_profiles(&names, current, format)?);
+        }
+
+        crate::cli::Command::Config {
+            command: crate::cli::ConfigCommand::Check { path },
+        } => {
+            let path = match path {
+                Some(path) => path,
+                None => xdg.get_config_file("config.toml"),
+            };
+            let mut config =
+                distrox_lib::configuration::Configuration::load_from_path(path).await?;
+            config.override_network(options.network);
+            let problems = config.validate();
+            crate::output::print_config_problems(&problems, format)?;
+            if !problems.is_empty() {
+                return Err(Error::ConfigProblems(problems.len()).into());
+            }
+            return Ok(());
+        }
+
+        crate::cli::Command::Config {
+            command: crate::cli::ConfigCommand::Reload,
+        } => distrox_lib::command::Command::ReloadConfig,
+
+        crate::cli::Command::ExportCar { head, path } => distrox_lib::command::Command::ExportCar {
+            head: head.map(|head| head.to_string()),
+            path: absolute(path)?,
+        },
+
+        crate::cli::Command::ImportCar { path } => distrox_lib::command::Command::ImportCar {
+            path: absolute(path)?,
+        },
+
+        crate::cli::Command::Post { text, file } => distrox_lib::command::Command::PostText {
+            text: read_text(text, file).await?,
+        },
+
+        crate::cli::Command::Timeline { limit } => {
+            distrox_lib::command::Command::Timeline { limit, after: None }
+        }
+
+        crate::cli::Command::Follow { author } => distrox_lib::command::Command::Follow { author },
+
+        crate::cli::Command::Unfollow { author } => {
+            distrox_lib::command::Command::Unfollow { author }
+        }
+
+        crate::cli::Command::Connect { multiaddr } => {
+            distrox_lib::command::Command::ConnectTo { uri: multiaddr }
+        }
+
+        crate::cli::Command::Peers => distrox_lib::command::Command::Peers,
+        crate::cli::Command::Whoami => distrox_lib::command::Command::Whoami,
+    };
+
+    let output = crate::client::request(&socket, xdg, options, command).await?;
+    Ok(crate::output::print(output, format)?)
+}
+
+async fn run_gui(
+    app: distrox_lib::application::Application,
+    options: distrox_lib::application::Options,
+    xdg_prefix: String,
+    profile: String,
+) -> Result<(), Error> {
+    let (sender, receiver) = distrox_lib::command::channel(100);
+    let (backend, backend_receiver) = tokio::sync::watch::channel(sender);
+    let (switch, switched) = tokio::sync::mpsc::unbounded_channel();
+    let (events, events_receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    let profiles = distrox_gui::Profiles {
+        names: distrox_lib::profile::list(&xdg_prefix)?,
+        current: profile.clone(),
+        switch: Some(switch),
+    };
+    let gui_task = tokio::task::spawn_blocking(|| {
+        distrox_gui::start(backend_receiver, events_receiver, profiles).map_err(Error::from)
+    })
+    .map(|r| match r {
+        Ok(res) => res,
+        Err(join) => Err(Error::Join(join)),
+    });
+    let profiles = ProfileOptions {
+        xdg_prefix,
+        started: profile,
+        options,
+    };
+    let app_task = run_profiles(app, receiver, backend, events, switched, profiles);
 
     tokio::try_join!(gui_task, app_task)?;
     Ok(())
 }
+
+/// How the applications of the profiles are loaded
+struct ProfileOptions {
+    xdg_prefix: String,
+
+    /// The profile distrox was started with
+    started: String,
+    options: distrox_lib::application::Options,
+}
+
+impl ProfileOptions {
+    /// Overrides of the network settings were meant for the profile distrox was started with, so
+    /// other profiles are loaded without them.
+    async fn load(&self, profile: &str) -> Result<distrox_lib::application::Application, Error> {
+        let xdg = distrox_lib::profile::xdg_directories(&self.xdg_prefix, Some(profile))?;
+        let options = if profile == self.started {
+            self.options.clone()
+        } else {
+            distrox_lib::application::Options::default()
+        };
+        Ok(distrox_lib::application::Application::load_from_xdg(xdg, options).await?)
+    }
+}
+
+/// Run the application of the current profile, replacing it whenever the user switches profiles
+///
+/// The running application is quit before the next one is loaded, so both never share the
+/// network. If the next profile cannot be loaded, the previous one is loaded again.
+async fn run_profiles(
+    app: distrox_lib::application::Application,
+    receiver: distrox_lib::command::CommandReceiver,
+    backend: tokio::sync::watch::Sender<distrox_lib::command::CommandSender>,
+    events: tokio::sync::mpsc::UnboundedSender<distrox_lib::event::Event>,
+    mut switched: tokio::sync::mpsc::UnboundedReceiver<distrox_gui::ProfileSwitch>,
+    profiles: ProfileOptions,
+) -> Result<(), Error> {
+    tokio::spawn(forward_events(app.events(), events.clone()));
+    let mut running = run_app(app, receiver);
+    let mut current = profiles.started.clone();
+
+    loop {
+        tokio::select! {
+            result = &mut running => return Ok(result?),
+
+            // The interface was closed
+            _ = backend.closed() => {
+                let sender = backend.borrow().clone();
+                let _ = sender.send(distrox_lib::command::Command::QuitApp).await;
+                return Ok(running.await?);
+            }
+
+            Some(switch) = switched.recv() => {
+                let (sender, receiver) = distrox_lib::command::channel(100);
+                let previous = backend.send_replace(sender);
+                let _ = previous.send(distrox_lib::command::Command::QuitApp).await;
+                (&mut running).await?;
+
+                let app = match profiles.load(&switch.profile).await {
+                    Ok(app) => {
+                        tracing::info!(profile = %switch.profile, "Switched profile");
+                        let _ = switch.done.send(Ok(()));
+                        current = switch.profile;
+                        app
+                    }
+                    Err(error) => {
+                        tracing::warn!(profile = %switch.profile, %error, "Failed to switch profile");
+                        let _ = switch.done.send(Err(error.to_string()));
+                        profiles.load(&current).await?
+                    }
+                };
+
+                tokio::spawn(forward_events(app.events(), events.clone()));
+                running = run_app(app, receiver);
+            }
+        }
+    }
+}
+
+/// Pass the events of an application on to the interface, until the application is gone
+async fn forward_events(
+    mut events: distrox_lib::event::EventReceiver,
+    to: tokio::sync::mpsc::UnboundedSender<distrox_lib::event::Event>,
+) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if to.send(event).is_err() {
+                    return;
+                }
+            }
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// The application is not `Send`, so it runs on the current task instead of being spawned
+fn run_app(
+    app: distrox_lib::application::Application,
+    receiver: distrox_lib::command::CommandReceiver,
+) -> futures::future::LocalBoxFuture<'static, Result<(), distrox_lib::error::Error>> {
+    async move { app.run(receiver).await }.boxed_local()
+}
+
+/// Log to stderr, or into a file in the state directory while the TUI draws on the terminal
+fn init_logging(
+    command: Option<&crate::cli::Command>,
+    xdg: &xdg::BaseDirectories,
+) -> Result<(), Error> {
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env());
+
+    if matches!(command, Some(crate::cli::Command::Tui)) {
+        let path = xdg
+            .place_state_file("tui.log")
+            .map_err(|source| Error::LogFile {
+                path: xdg.get_state_home().join("tui.log"),
+                source,
+            })?;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|source| Error::LogFile { path, source })?;
+        subscriber
+            .with_ansi(false)
+            .with_writer(std::sync::Mutex::new(file))
+            .init();
+    } else {
+        // Keep stdout for the output of commands
+        subscriber.with_writer(std::io::stderr).init();
+    }
+    Ok(())
+}
+
+async fn run_tui(app: distrox_lib::application::Application) -> Result<(), Error> {
+    let (sender, receiver) = distrox_lib::command::channel(100);
+
+    let tui_task = distrox_tui::start(sender, app.events()).map(|r| r.map_err(Error::from));
+    let app_task = app.run(receiver).map(|r| r.map_err(Error::from));
+
+    tokio::try_join!(tui_task, app_task)?;
+    Ok(())
+}
+
+/// Make `path` absolute, a daemon handling the command has another working directory
+fn absolute(path: std::path::PathBuf) -> Result<std::path::PathBuf, Error> {
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    Ok(cwd.join(path))
+}
+
+/// The text of a post, from the argument, a file or stdin
+async fn read_text(
+    text: Option<String>,
+    file: Option<std::path::PathBuf>,
+) -> Result<String, Error> {
+    use tokio::io::AsyncReadExt;
+
+    match (text, file) {
+        (Some(text), _) if text != "-" => Ok(text),
+        (_, Some(file)) => {
+            tokio::fs::read_to_string(&file)
+                .await
+                .map_err(|source| Error::ReadingText {
+                    path: file.display().to_string(),
+                    source,
+                })
+        }
+        _ => {
+            let mut text = String::new();
+            tokio::io::stdin()
+                .read_to_string(&mut text)
+                .await
+                .map_err(|source| Error::ReadingText {
+                    path: "stdin".to_string(),
+                    source,
+                })?;
+            Ok(text)
+        }
+    }
+}
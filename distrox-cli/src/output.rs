@@ -0,0 +1,216 @@
+use std::io::Write;
+
+use distrox_lib::application::Setup;
+use distrox_lib::command::CommandOutput;
+use distrox_lib::configuration::ConfigProblem;
+use distrox_lib::timeline::PostView;
+use serde_json::json;
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// For humans
+    Text,
+
+    /// One JSON object, or one JSON object per line for lists like timelines and peers
+    Json,
+}
+
+pub fn print(output: CommandOutput, format: Format) -> Result<(), Error> {
+    match format {
+        Format::Text => {
+            print_text(output);
+            Ok(())
+        }
+        Format::Json => print_json(output),
+    }
+}
+
+pub fn print_setup(setup: &Setup, format: Format) -> Result<(), Error> {
+    match format {
+        Format::Text => {
+            println!("device  {}", setup.device_id);
+            for (name, path) in [
+                ("config", &setup.config_path),
+                ("state ", &setup.state_path),
+                ("key   ", &setup.device_key_path),
+            ] {
+                let created = if setup.created.contains(path) {
+                    " (created)"
+                } else {
+                    ""
+                };
+                println!("{name}  {}{created}", path.display());
+            }
+            Ok(())
+        }
+        Format::Json => write_line(&json!({
+            "device_id": setup.device_id.to_string(),
+            "config_path": setup.config_path,
+            "state_path": setup.state_path,
+            "device_key_path": setup.device_key_path,
+            "created": setup.created,
+        })),
+    }
+}
+
+pub fn print_profiles(names: &[String], current: &str, format: Format) -> Result<(), Error> {
+    match format {
+        Format::Text => {
+            for name in names {
+                let marker = if name == current { "*" } else { " " };
+                println!("{marker} {name}");
+            }
+            Ok(())
+        }
+        Format::Json => names
+            .iter()
+            .try_for_each(|name| write_line(&json!({ "name": name, "current": name == current }))),
+    }
+}
+
+/// Problems are printed one per line, nothing is printed for a valid configuration
+pub fn print_config_problems(problems: &[ConfigProblem], format: Format) -> Result<(), Error> {
+    match format {
+        Format::Text => {
+            for problem in problems {
+                println!("{problem}");
+            }
+            Ok(())
+        }
+        Format::Json => problems.iter().try_for_each(write_line),
+    }
+}
+
+fn print_json(output: CommandOutput) -> Result<(), Error> {
+    match output {
+        CommandOutput::Timeline { entries, .. } => entries.iter().try_for_each(write_line),
+        CommandOutput::Peers { peers } => peers.iter().try_for_each(write_line),
+        output => write_line(&output),
+    }
+}
+
+fn write_line(value: &impl serde::Serialize) -> Result<(), Error> {
+    let line = serde_json::to_string(value).map_err(Error::SerializingOutput)?;
+
+    writeln!(std::io::stdout().lock(), "{line}").map_err(Error::WritingOutput)
+}
+
+fn print_text(output: CommandOutput) {
+    match output {
+        CommandOutput::Done => {}
+
+        CommandOutput::Node { node_id } => println!("{node_id}"),
+
+        CommandOutput::Account {
+            account_id,
+            account_record,
+        } => {
+            println!("account {account_id}");
+            println!("record  {account_record}");
+        }
+
+        CommandOutput::GarbageCollected {
+            removed_blocks,
+            freed_bytes,
+        } => println!("Removed {removed_blocks} blocks, freed {freed_bytes} bytes"),
+
+        CommandOutput::Timeline { entries, .. } => {
+            for entry in entries {
+                match entry.post {
+                    PostView::Original {
+                        content_mime,
+                        timestamp,
+                        text,
+                        ..
+                    } => {
+                        let timestamp = timestamp
+                            .format(&time::format_description::well_known::Rfc3339)
+                            .unwrap_or_else(|_| timestamp.to_string());
+                        println!("{timestamp}  {}  {}", entry.author, entry.node_id);
+                        match text {
+                            Some(text) => println!("{}", text.trim_end()),
+                            None => println!("[{content_mime}]"),
+                        }
+                    }
+                    PostView::Repost {
+                        node_id, author, ..
+                    } => {
+                        let of = author.map(|a| format!(" of {a}")).unwrap_or_default();
+                        println!("{}  reposted {node_id}{of}", entry.author);
+                    }
+                    PostView::Announce {
+                        node_id, author, ..
+                    } => {
+                        let of = author.map(|a| format!(" of {a}")).unwrap_or_default();
+                        println!("{}  announced {node_id}{of}", entry.author);
+                    }
+                }
+                println!();
+            }
+        }
+
+        CommandOutput::Peers { peers } => {
+            for peer in peers {
+                println!("{} {}", peer.peer_id, peer.addrs.join(" "));
+            }
+        }
+
+        CommandOutput::Identity {
+            device_id,
+            account_id,
+            account_record,
+        } => {
+            println!("device  {device_id}");
+            if let Some(account_id) = account_id {
+                println!("account {account_id}");
+            }
+            if let Some(account_record) = account_record {
+                println!("record  {account_record}");
+            }
+        }
+
+        CommandOutput::ConfigReloaded(changes) => {
+            for key in changes.applied {
+                println!("applied          {key}");
+            }
+            for key in changes.restart_required {
+                println!("restart required {key}");
+            }
+        }
+
+        CommandOutput::NetworkSettings(settings) => {
+            println!("storage_path    {}", settings.storage_path.display());
+            for addr in settings.bootstrap_nodes {
+                println!("bootstrap_node  {addr}");
+            }
+            for addr in settings.listening_addrs {
+                println!("listening_addr  {addr}");
+            }
+            if let Some(quota_bytes) = settings.quota_bytes {
+                println!("quota_bytes     {quota_bytes}");
+            }
+            println!("mdns            {}", settings.mdns);
+            println!("upnp            {}", settings.upnp);
+            for key in settings.overridden {
+                println!("overridden      {key}");
+            }
+        }
+
+        CommandOutput::CarExported { path, blocks } => {
+            println!("Exported {blocks} blocks to {}", path.display());
+        }
+
+        CommandOutput::CarImported {
+            path,
+            roots,
+            blocks,
+        } => {
+            println!("Imported {blocks} blocks from {}", path.display());
+            for root in roots {
+                println!("{root}");
+            }
+        }
+    }
+}
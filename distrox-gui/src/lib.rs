@@ -1,22 +1,60 @@
 slint::include_modules!();
 
 pub mod error;
+mod settings;
+mod timeline;
 
 use crate::error::Error;
 use distrox_lib::command::Command;
 use distrox_lib::command::CommandSender;
+use distrox_lib::event::Event;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::watch;
 
-pub fn start(sender: CommandSender) -> Result<(), Error> {
+/// The profiles the user can switch between
+pub struct Profiles {
+    pub names: Vec<String>,
+    pub current: String,
+
+    /// Gets the profiles the user switches to, the frontend then replaces the backend
+    ///
+    /// Without it, the interface offers no way to switch.
+    pub switch: Option<UnboundedSender<ProfileSwitch>>,
+}
+
+/// The user asked to switch to another profile
+pub struct ProfileSwitch {
+    pub profile: String,
+
+    /// Answered once the backend of `profile` runs, or with why it could not be started
+    pub done: tokio::sync::oneshot::Sender<Result<(), String>>,
+}
+
+/// Run the interface until its window is closed
+///
+/// Commands go to the backend currently in `backend`, which changes when the profile is switched.
+/// `events` are those of whichever backend is current.
+pub fn start(
+    backend: watch::Receiver<CommandSender>,
+    events: UnboundedReceiver<Event>,
+    profiles: Profiles,
+) -> Result<(), Error> {
     let ui = AppWindow::new()?;
-    install_callbacks(&ui, sender)?;
+    install_callbacks(&ui, backend, events, profiles)?;
     ui.run().map_err(Error::from)
 }
 
-fn install_callbacks(ui: &AppWindow, sender: CommandSender) -> Result<(), Error> {
+fn install_callbacks(
+    ui: &AppWindow,
+    backend: watch::Receiver<CommandSender>,
+    events: UnboundedReceiver<Event>,
+    profiles: Profiles,
+) -> Result<(), Error> {
     {
-        let sender = sender.clone();
+        let backend = backend.clone();
         ui.on_post_text_content(move |text| {
-            let sender = sender.clone();
+            let sender = backend.borrow().clone();
             tokio::spawn(async move {
                 let text = text.to_string();
                 let _ = sender.send(Command::PostText { text }).await;
@@ -25,9 +63,9 @@ fn install_callbacks(ui: &AppWindow, sender: CommandSender) -> Result<(), Error>
     }
 
     {
-        let sender = sender.clone();
+        let backend = backend.clone();
         ui.on_start_connecting(move |text| {
-            let sender = sender.clone();
+            let sender = backend.borrow().clone();
             tokio::spawn(async move {
                 let uri = text.to_string();
                 let _ = sender.send(Command::ConnectTo { uri }).await;
@@ -35,5 +73,57 @@ fn install_callbacks(ui: &AppWindow, sender: CommandSender) -> Result<(), Error>
         });
     }
 
+    {
+        let names = profiles
+            .names
+            .iter()
+            .map(slint::SharedString::from)
+            .collect::<Vec<_>>();
+        ui.set_profiles(slint::ModelRc::new(slint::VecModel::from(names)));
+        ui.set_current_profile(profiles.current.into());
+        ui.set_can_switch_profile(profiles.switch.is_some());
+
+        if let Some(switch) = profiles.switch {
+            let weak = ui.as_weak();
+            ui.on_switch_profile(move |profile| {
+                let (done, result) = tokio::sync::oneshot::channel();
+                let request = ProfileSwitch {
+                    profile: profile.to_string(),
+                    done,
+                };
+                if switch.send(request).is_err() {
+                    return;
+                }
+
+                if let Some(ui) = weak.upgrade() {
+                    ui.set_switching_profile(true);
+                    ui.set_profile_status(format!("Switching to {profile}").into());
+                }
+
+                let weak = weak.clone();
+                tokio::spawn(async move {
+                    let result = result
+                        .await
+                        .unwrap_or_else(|_| Err("distrox is shutting down".to_string()));
+                    let _ = weak.upgrade_in_event_loop(move |ui| {
+                        ui.set_switching_profile(false);
+                        match result {
+                            Ok(()) => {
+                                ui.set_current_profile(profile);
+                                ui.set_profile_status(Default::default());
+                            }
+                            Err(error) => ui.set_profile_status(
+                                format!("Cannot switch to {profile}: {error}").into(),
+                            ),
+                        }
+                    });
+                });
+            });
+        }
+    }
+
+    timeline::install(ui, backend.clone(), events);
+    settings::install(ui, backend);
+
     Ok(())
 }
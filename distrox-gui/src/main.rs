@@ -4,12 +4,19 @@ use tracing::debug;
 async fn main() -> Result<(), distrox_gui::error::Error> {
     tracing_subscriber::fmt::init();
 
-    let (sender, mut receiver) = tokio::sync::mpsc::channel(100);
+    let (sender, mut receiver) = distrox_lib::command::channel(100);
     tokio::spawn(async move {
-        while let Some(message) = receiver.recv().await {
-            debug!("Reveived: {message:?}");
+        while let Some(request) = receiver.recv().await {
+            debug!("Reveived: {:?}", request.command);
         }
     });
 
-    distrox_gui::start(sender)
+    let (_backend, backend_receiver) = tokio::sync::watch::channel(sender);
+    let (_events, events) = tokio::sync::mpsc::unbounded_channel();
+    let profiles = distrox_gui::Profiles {
+        names: vec![distrox_lib::profile::DEFAULT_PROFILE.to_string()],
+        current: distrox_lib::profile::DEFAULT_PROFILE.to_string(),
+        switch: None,
+    };
+    distrox_gui::start(backend_receiver, events, profiles)
 }
@@ -0,0 +1,151 @@
+use distrox_lib::command::Command;
+use distrox_lib::command::CommandOutput;
+use distrox_lib::command::CommandResult;
+use distrox_lib::command::CommandSender;
+use distrox_lib::configuration::NetworkSettings;
+use slint::ComponentHandle;
+use tokio::sync::watch;
+
+use crate::AppWindow;
+
+/// Show the `[network]` settings of the backend, and save them when the user asks to
+///
+/// The settings are loaded again whenever the backend changes.
+pub(crate) fn install(ui: &AppWindow, backend: watch::Receiver<CommandSender>) {
+    {
+        let weak = ui.as_weak();
+        let mut backend = backend.clone();
+        tokio::spawn(async move {
+            loop {
+                let sender = backend.borrow_and_update().clone();
+                let ui = weak.clone();
+                load(ui, sender).await;
+                if backend.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    {
+        let weak = ui.as_weak();
+        let backend = backend.clone();
+        ui.on_reset_network_settings(move || {
+            let sender = backend.borrow().clone();
+            tokio::spawn(load(weak.clone(), sender));
+        });
+    }
+
+    {
+        let weak = ui.as_weak();
+        ui.on_save_network_settings(move || {
+            let Some(ui) = weak.upgrade() else {
+                return;
+            };
+
+            let settings = match read_form(&ui) {
+                Ok(settings) => settings,
+                Err(message) => {
+                    ui.set_settings_status(message.into());
+                    return;
+                }
+            };
+
+            let sender = backend.borrow().clone();
+            let weak = weak.clone();
+            tokio::spawn(async move {
+                let result = sender
+                    .request(Command::SetNetworkSettings { settings })
+                    .await;
+                let _ = weak.upgrade_in_event_loop(move |ui| {
+                    ui.set_settings_status(save_status(result).into());
+                });
+            });
+        });
+    }
+}
+
+async fn load(ui: slint::Weak<AppWindow>, sender: CommandSender) {
+    let result = sender.request(Command::NetworkSettings).await;
+    let _ = ui.upgrade_in_event_loop(move |ui| match result {
+        Ok(CommandOutput::NetworkSettings(settings)) => {
+            fill_form(&ui, settings);
+            ui.set_settings_status(Default::default());
+        }
+        Ok(output) => tracing::warn!(?output, "Unexpected answer to NetworkSettings"),
+        Err(error) => ui.set_settings_status(format!("Cannot load settings: {error}").into()),
+    });
+}
+
+fn fill_form(ui: &AppWindow, settings: NetworkSettings) {
+    ui.set_storage_path(settings.storage_path.display().to_string().into());
+    ui.set_bootstrap_nodes(settings.bootstrap_nodes.join("\n").into());
+    ui.set_listening_addrs(settings.listening_addrs.join("\n").into());
+    ui.set_quota_bytes(
+        settings
+            .quota_bytes
+            .map(|quota| quota.to_string())
+            .unwrap_or_default()
+            .into(),
+    );
+    ui.set_mdns(settings.mdns);
+    ui.set_upnp(settings.upnp);
+
+    let overridden = |key: &str| settings.overridden.iter().any(|k| k == key);
+    ui.set_storage_path_overridden(overridden("network.storage_path"));
+    ui.set_bootstrap_nodes_overridden(overridden("network.bootstrap_nodes"));
+    ui.set_listening_addrs_overridden(overridden("network.listening_addrs"));
+    ui.set_quota_bytes_overridden(overridden("network.quota_bytes"));
+}
+
+/// The settings the user entered, everything beyond their form is checked by the backend
+fn read_form(ui: &AppWindow) -> Result<NetworkSettings, String> {
+    let lines = |text: slint::SharedString| {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()
+    };
+
+    let storage_path = ui.get_storage_path().trim().to_string();
+    if storage_path.is_empty() {
+        return Err("The storage path must not be empty".to_string());
+    }
+
+    let quota_bytes =
+        match ui.get_quota_bytes().trim() {
+            "" => None,
+            quota => Some(quota.parse().map_err(|_| {
+                format!("The storage quota must be a number of bytes, not {quota:?}")
+            })?),
+        };
+
+    Ok(NetworkSettings {
+        storage_path: storage_path.into(),
+        bootstrap_nodes: lines(ui.get_bootstrap_nodes()),
+        listening_addrs: lines(ui.get_listening_addrs()),
+        quota_bytes,
+        mdns: ui.get_mdns(),
+        upnp: ui.get_upnp(),
+        overridden: Vec::new(),
+    })
+}
+
+fn save_status(result: CommandResult) -> String {
+    match result {
+        Ok(CommandOutput::ConfigReloaded(changes)) if !changes.restart_required.is_empty() => {
+            format!(
+                "Saved, restart distrox to apply {}",
+                changes.restart_required.join(", ")
+            )
+        }
+        Ok(_) => "Saved".to_string(),
+        Err(distrox_lib::error::Error::InvalidConfiguration(problems)) => problems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Err(error) => format!("Cannot save settings: {error}"),
+    }
+}
@@ -0,0 +1,193 @@
+use distrox_lib::command::Command;
+use distrox_lib::command::CommandOutput;
+use distrox_lib::command::CommandSender;
+use distrox_lib::event::Event;
+use distrox_lib::timeline::PostView;
+use distrox_lib::timeline::TimelineCursor;
+use distrox_lib::timeline::TimelineEntry;
+use slint::ComponentHandle;
+use slint::Model;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::watch;
+
+use crate::AppWindow;
+use crate::Post;
+
+/// How many more posts are loaded when the end of the timeline is scrolled to
+const PAGE: usize = 50;
+
+/// How long to wait for further changes of the timelines before loading it again
+const RELOAD_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Fill the timeline of the main page and keep it up to date
+///
+/// The timeline is loaded again shortly after timelines change in the backend and when the backend
+/// changes. When the user scrolls to its end, it is continued after the last shown post.
+pub(crate) fn install(
+    ui: &AppWindow,
+    mut backend: watch::Receiver<CommandSender>,
+    mut events: UnboundedReceiver<Event>,
+) {
+    ui.set_posts(slint::ModelRc::new(slint::VecModel::<Post>::default()));
+
+    let (more, mut more_requested) = tokio::sync::mpsc::unbounded_channel();
+    let weak = ui.as_weak();
+    ui.on_post_shown(move |index| {
+        let Some(ui) = weak.upgrade() else {
+            return;
+        };
+        if usize::try_from(index + 1).ok() == Some(ui.get_posts().row_count()) {
+            let _ = more.send(());
+        }
+    });
+
+    let weak = ui.as_weak();
+    tokio::spawn(async move {
+        // The posts already shown, and where the timeline continues after them
+        let mut shown = std::collections::HashSet::new();
+        let mut next: Option<TimelineCursor> = None;
+        let mut reload = true;
+        let mut reload_at: Option<tokio::time::Instant> = None;
+
+        loop {
+            if reload {
+                // Load as much as is shown, so the list does not shrink under the user
+                let limit = shown.len().max(PAGE);
+                let sender = backend.borrow_and_update().clone();
+                match sender
+                    .request(Command::Timeline { limit, after: None })
+                    .await
+                {
+                    Ok(CommandOutput::Timeline {
+                        entries,
+                        next: cursor,
+                    }) => {
+                        shown = entries.iter().map(|e| e.node_id.clone()).collect();
+                        next = cursor;
+                        let _ = weak.upgrade_in_event_loop(move |ui| show(&ui, entries));
+                    }
+                    Ok(output) => tracing::warn!(?output, "Unexpected answer to Timeline"),
+                    Err(error) => tracing::warn!(%error, "Failed to load timeline"),
+                }
+            }
+
+            reload = tokio::select! {
+                changed = backend.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    shown.clear();
+                    reload_at = None;
+                    true
+                }
+
+                Some(event) = events.recv() => {
+                    // Changes come in bursts while timelines sync, reload once they settled
+                    if matches!(event, Event::TimelineChanged { .. }) && reload_at.is_none() {
+                        reload_at = Some(tokio::time::Instant::now() + RELOAD_DELAY);
+                    }
+                    false
+                }
+
+                () = sleep_until(reload_at) => {
+                    reload_at = None;
+                    true
+                }
+
+                Some(()) = more_requested.recv() => {
+                    if let Some(after) = next.take() {
+                        let sender = backend.borrow().clone();
+                        let command = Command::Timeline { limit: PAGE, after: Some(after) };
+                        match sender.request(command).await {
+                            Ok(CommandOutput::Timeline { entries, next: cursor }) => {
+                                next = cursor;
+                                let entries = entries
+                                    .into_iter()
+                                    .filter(|e| shown.insert(e.node_id.clone()))
+                                    .collect();
+                                let _ = weak.upgrade_in_event_loop(move |ui| append(&ui, entries));
+                            }
+                            Ok(output) => tracing::warn!(?output, "Unexpected answer to Timeline"),
+                            Err(error) => tracing::warn!(%error, "Failed to load more of the timeline"),
+                        }
+                    }
+                    false
+                }
+            };
+        }
+    });
+}
+
+/// Wait until the deadline, or forever if there is none
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Replace the posts in the model, so the list keeps its scroll position
+fn show(ui: &AppWindow, entries: Vec<TimelineEntry>) {
+    let posts = ui.get_posts();
+    let Some(model) = posts.as_any().downcast_ref::<slint::VecModel<Post>>() else {
+        return;
+    };
+
+    let posts = entries.into_iter().map(post).collect::<Vec<_>>();
+    let unchanged =
+        model.row_count() == posts.len() && model.iter().zip(posts.iter()).all(|(a, b)| a == *b);
+    if !unchanged {
+        model.set_vec(posts);
+    }
+}
+
+/// Add the posts to the end of the model
+fn append(ui: &AppWindow, entries: Vec<TimelineEntry>) {
+    let posts = ui.get_posts();
+    let Some(model) = posts.as_any().downcast_ref::<slint::VecModel<Post>>() else {
+        return;
+    };
+    entries.into_iter().map(post).for_each(|p| model.push(p));
+}
+
+fn post(entry: TimelineEntry) -> Post {
+    let (timestamp, text, attribution) = match entry.post {
+        PostView::Original {
+            content_mime,
+            timestamp,
+            text,
+            ..
+        } => {
+            let timestamp = timestamp
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_else(|_| timestamp.to_string());
+            let text = text.unwrap_or_else(|| format!("[{content_mime}]"));
+            (timestamp, text, String::new())
+        }
+        PostView::Repost {
+            node_id, author, ..
+        } => {
+            let attribution = match author {
+                Some(author) => format!("Reposted from {author}"),
+                None => format!("Reposted {node_id}, from an unknown author"),
+            };
+            (String::new(), String::new(), attribution)
+        }
+        PostView::Announce {
+            node_id, author, ..
+        } => {
+            let attribution = match author {
+                Some(author) => format!("Announced a post of {author}"),
+                None => format!("Announced {node_id}, from an unknown author"),
+            };
+            (String::new(), String::new(), attribution)
+        }
+    };
+
+    Post {
+        author: entry.author.into(),
+        timestamp: timestamp.into(),
+        text: text.trim_end().into(),
+        attribution: attribution.into(),
+    }
+}
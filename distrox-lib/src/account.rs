@@ -0,0 +1,162 @@
+use distrox_types::account::Account;
+use distrox_types::account::DeviceDelegation;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use libp2p::identity::Keypair;
+use libp2p::identity::PublicKey;
+use libp2p::PeerId;
+
+use crate::error::Error;
+
+/// Multicodec of raw binary data
+const RAW_CODEC: u64 = 0x55;
+
+/// The PeerId derived from the public key of the account
+pub fn account_id(account: &Account) -> Result<PeerId, Error> {
+    PublicKey::try_decode_protobuf(&account.public_key)
+        .map(|key| key.to_peer_id())
+        .map_err(Error::DecodingKeypair)
+}
+
+/// The pubsub topic the devices of an account use to exchange their heads
+pub fn sync_topic(account_id: &PeerId) -> String {
+    format!("/distrox/account/{account_id}/sync/0")
+}
+
+/// The block whose CID is the DHT key that peers holding the content of an account provide
+///
+/// Everybody can derive it from the account id, so followers can look up who has the timeline of
+/// an author, even while none of the devices of that author is online.
+pub fn provider_block(account_id: &PeerId) -> Result<rust_ipfs::Block, Error> {
+    use libipld::multihash::MultihashDigest;
+
+    let data = format!("/distrox/account/{account_id}/provider/0").into_bytes();
+    let hash = libipld::multihash::Code::Sha2_256.digest(&data);
+    rust_ipfs::Block::new(cid::Cid::new_v1(RAW_CODEC, hash), data).map_err(Error::from)
+}
+
+/// Create a new account, with `device` being its first device
+pub fn new_account(account_key: &Keypair, device: PeerId) -> Result<Account, Error> {
+    let mut account = Account {
+        protocol_version: distrox_types::protocol::ProtocolVersion(0),
+        public_key: account_key.public().encode_protobuf(),
+        devices: Vec::new(),
+    };
+
+    delegate(&mut account, account_key, device)?;
+    Ok(account)
+}
+
+/// Allow `device` to post on behalf of `account`
+pub fn delegate(account: &mut Account, account_key: &Keypair, device: PeerId) -> Result<(), Error> {
+    if account_key.public().encode_protobuf() != account.public_key {
+        return Err(Error::NotAccountKey);
+    }
+
+    if is_delegated(account, &device) {
+        return Ok(());
+    }
+
+    let device = device.to_bytes();
+    let signature = account_key.sign(&device).map_err(Error::Signing)?;
+    account.devices.push(DeviceDelegation { device, signature });
+    Ok(())
+}
+
+/// Check that every delegation in the account was signed with the account key
+pub fn verify(account: &Account) -> Result<(), Error> {
+    let public_key =
+        PublicKey::try_decode_protobuf(&account.public_key).map_err(Error::DecodingKeypair)?;
+
+    account
+        .devices
+        .iter()
+        .all(|delegation| public_key.verify(&delegation.device, &delegation.signature))
+        .then_some(())
+        .ok_or(Error::InvalidDelegation)
+}
+
+pub fn is_delegated(account: &Account, device: &PeerId) -> bool {
+    let device = device.to_bytes();
+    account.devices.iter().any(|d| d.device == device)
+}
+
+/// The running synchronization between the devices of one account
+pub(crate) struct AccountSync {
+    pub(crate) account: Account,
+    pub(crate) topic: String,
+    pub(crate) messages: BoxStream<'static, libp2p::gossipsub::Message>,
+    pub(crate) events: BoxStream<'static, rust_ipfs::PubsubEvent>,
+}
+
+pub(crate) enum AccountSyncItem {
+    Message(libp2p::gossipsub::Message),
+    Event(rust_ipfs::PubsubEvent),
+}
+
+impl AccountSync {
+    async fn next(&mut self) -> Option<AccountSyncItem> {
+        tokio::select! {
+            Some(message) = self.messages.next() => Some(AccountSyncItem::Message(message)),
+            Some(event) = self.events.next() => Some(AccountSyncItem::Event(event)),
+            else => None,
+        }
+    }
+}
+
+/// Wait for the next item of the synchronization, or forever if there is no account
+pub(crate) async fn next_sync_item(sync: &mut Option<AccountSync>) -> Option<AccountSyncItem> {
+    match sync {
+        Some(sync) => sync.next().await,
+        None => futures::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delegation_verifies() {
+        let account_key = Keypair::generate_ed25519();
+        let device1 = PeerId::random();
+        let device2 = PeerId::random();
+
+        let mut account = new_account(&account_key, device1).unwrap();
+        delegate(&mut account, &account_key, device2).unwrap();
+
+        assert!(verify(&account).is_ok());
+        assert!(is_delegated(&account, &device1));
+        assert!(is_delegated(&account, &device2));
+        assert!(!is_delegated(&account, &PeerId::random()));
+    }
+
+    #[test]
+    fn test_provider_block_is_stable() {
+        let account = PeerId::random();
+        let block = provider_block(&account).unwrap();
+
+        assert_eq!(block.cid(), provider_block(&account).unwrap().cid());
+        assert_ne!(
+            block.cid(),
+            provider_block(&PeerId::random()).unwrap().cid()
+        );
+    }
+
+    #[test]
+    fn test_forged_delegation_fails() {
+        let account_key = Keypair::generate_ed25519();
+        let other_key = Keypair::generate_ed25519();
+
+        let mut account = new_account(&account_key, PeerId::random()).unwrap();
+        assert!(matches!(
+            delegate(&mut account, &other_key, PeerId::random()),
+            Err(Error::NotAccountKey)
+        ));
+
+        let device = PeerId::random().to_bytes();
+        let signature = other_key.sign(&device).unwrap();
+        account.devices.push(DeviceDelegation { device, signature });
+        assert!(matches!(verify(&account), Err(Error::InvalidDelegation)));
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use distrox_types::account::AccountMessage;
+use futures::stream::BoxStream;
+use futures::stream::SelectAll;
+use futures::StreamExt;
+use libipld::prelude::Codec;
+use libp2p::PeerId;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing::debug;
+
+use crate::error::Error;
+use crate::event::Discovery;
+use crate::network::Network;
+
+/// How many announced posts are remembered to drop duplicates
+const SEEN_CAPACITY: usize = 4096;
+
+/// How many announcements a single peer may send per `RATE_LIMIT_WINDOW`
+const RATE_LIMIT: usize = 10;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long to listen on the topic of an announced author for a head to verify against
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long checking whether an announced node is in the timeline of its author may take
+const CHECK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many authors of announcements are verified at the same time
+const MAX_VERIFICATIONS: usize = 8;
+
+/// The topic everybody publishes announcements on
+pub(crate) fn global_topic() -> String {
+    "/distrox/announce/0".to_string()
+}
+
+pub(crate) fn community_topic(community: &str) -> String {
+    format!("/distrox/announce/community/{community}/0")
+}
+
+/// The subscriptions to the announcement topics
+pub(crate) struct AnnounceSync {
+    messages: SelectAll<BoxStream<'static, libp2p::gossipsub::Message>>,
+    filter: AnnouncementFilter,
+
+    /// Authors of announcements that are being verified, see `verify`
+    verifications: HashMap<PeerId, Verification>,
+    verified: (UnboundedSender<Discovery>, UnboundedReceiver<Discovery>),
+}
+
+/// What the announcement topics deliver
+pub(crate) enum AnnounceItem {
+    /// An announcement that is neither a duplicate nor over the rate limit of its sender
+    Received(PeerId, libp2p::gossipsub::Message),
+
+    /// An announcement that turned out to be from the timeline of its author
+    Verified(Discovery),
+}
+
+struct Verification {
+    candidates: UnboundedSender<Discovery>,
+    cancel: tokio::sync::oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl AnnounceSync {
+    pub(crate) async fn subscribe(
+        network: &Network,
+        communities: &[String],
+    ) -> Result<Self, Error> {
+        let topics = std::iter::once(global_topic())
+            .chain(communities.iter().map(|c| community_topic(c)))
+            .collect::<Vec<_>>();
+
+        let mut messages = SelectAll::new();
+        for topic in topics.iter() {
+            messages.push(network.subscribe(topic.clone()).await?);
+        }
+
+        Ok(AnnounceSync {
+            messages,
+            filter: AnnouncementFilter::new(),
+            verifications: HashMap::new(),
+            verified: tokio::sync::mpsc::unbounded_channel(),
+        })
+    }
+
+    pub(crate) async fn next(&mut self) -> Option<AnnounceItem> {
+        loop {
+            tokio::select! {
+                message = self.messages.next() => {
+                    let message = message?;
+                    let Some(source) = message.source else {
+                        continue;
+                    };
+
+                    if self.filter.admits(source, &message.data, Instant::now()) {
+                        return Some(AnnounceItem::Received(source, message));
+                    }
+                }
+
+                Some(discovery) = self.verified.1.recv() => {
+                    return Some(AnnounceItem::Verified(discovery));
+                }
+            }
+        }
+    }
+
+    /// Check in the background whether the announced node is in the timeline of its author
+    ///
+    /// The author's topic is subscribed to, which makes their devices publish their account
+    /// record and heads. If the node is in the history of a head published by a delegated
+    /// device, the discovery is returned by `next`.
+    pub(crate) fn verify(&mut self, network: &Network, discovery: Discovery) {
+        self.verifications
+            .retain(|_, verification| !verification.task.is_finished());
+
+        let author = discovery.author;
+        let discovery = match self.verifications.get(&author) {
+            Some(verification) => match verification.candidates.send(discovery) {
+                Ok(()) => return,
+                // The verification is about to end, start another one
+                Err(error) => error.0,
+            },
+            None => discovery,
+        };
+
+        if self.verifications.len() >= MAX_VERIFICATIONS {
+            debug!(%author, "Dropping announcement, too many authors being verified");
+            return;
+        }
+
+        let (candidates, candidates_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (cancel, cancelled) = tokio::sync::oneshot::channel();
+        let _ = candidates.send(discovery);
+
+        let network = network.clone();
+        let verified = self.verified.0.clone();
+        let task = tokio::spawn(async move {
+            verify_author(&network, author, candidates_receiver, cancelled, verified).await;
+        });
+
+        self.verifications.insert(
+            author,
+            Verification {
+                candidates,
+                cancel,
+                task,
+            },
+        );
+    }
+
+    /// Stop verifying announcements of `author`, and wait until their topic is unsubscribed
+    pub(crate) async fn cancel_verification(&mut self, author: &PeerId) {
+        if let Some(verification) = self.verifications.remove(author) {
+            let _ = verification.cancel.send(());
+            let _ = verification.task.await;
+        }
+    }
+
+    /// Remember an announcement we sent ourselves, so it is not reported back to us
+    pub(crate) fn mark_seen(&mut self, data: &[u8]) {
+        self.filter.mark_seen(data);
+    }
+}
+
+impl Drop for AnnounceSync {
+    fn drop(&mut self) {
+        for verification in self.verifications.values() {
+            verification.task.abort();
+        }
+    }
+}
+
+/// Wait for the next announcement, or forever if we do not listen for announcements
+pub(crate) async fn next_announcement(sync: &mut Option<AnnounceSync>) -> Option<AnnounceItem> {
+    match sync {
+        Some(sync) => sync.next().await,
+        None => futures::future::pending().await,
+    }
+}
+
+async fn verify_author(
+    network: &Network,
+    author: PeerId,
+    mut candidates: UnboundedReceiver<Discovery>,
+    cancelled: tokio::sync::oneshot::Receiver<()>,
+    verified: UnboundedSender<Discovery>,
+) {
+    let topic = crate::account::sync_topic(&author);
+    let mut messages = match network.subscribe(topic.clone()).await {
+        Ok(messages) => messages,
+        Err(error) => {
+            debug!(%author, ?error, "Cannot listen to topic of announced author");
+            return;
+        }
+    };
+
+    let verification = async {
+        let heads = tokio::time::timeout(
+            VERIFY_TIMEOUT,
+            authentic_heads(network, author, &mut messages),
+        )
+        .await
+        .unwrap_or(Ok(Vec::new()))?;
+
+        // Announcements arriving from now on need a new verification
+        candidates.close();
+        while let Some(discovery) = candidates.recv().await {
+            if is_in_timeline(network, &heads, discovery.node_id).await {
+                let _ = verified.send(discovery);
+            } else {
+                debug!(%author, node_id = %discovery.node_id, "Dropping unverified announcement");
+            }
+        }
+        Ok::<_, Error>(())
+    };
+
+    tokio::select! {
+        result = verification => {
+            if let Err(error) = result {
+                debug!(%author, ?error, "Failed to verify announcements");
+            }
+        }
+        _ = cancelled => {}
+    }
+
+    // Unsubscribing ends the stream, so dropping it does not unsubscribe again later, when the
+    // topic might be subscribed to for following the author
+    if let Err(error) = network.unsubscribe(&topic).await {
+        debug!(%author, ?error, "Failed to leave topic of announced author");
+    }
+    let _ = tokio::time::timeout(Duration::from_secs(1), async {
+        while messages.next().await.is_some() {}
+    })
+    .await;
+}
+
+/// Listen on the topic of `author` until a device delegated by their account published a head
+async fn authentic_heads(
+    network: &Network,
+    author: PeerId,
+    messages: &mut BoxStream<'static, libp2p::gossipsub::Message>,
+) -> Result<Vec<cid::Cid>, Error> {
+    let mut account = None;
+    let mut heads = Vec::new();
+
+    while let Some(message) = messages.next().await {
+        match libipld::cbor::DagCborCodec.decode(&message.data)? {
+            AccountMessage::Account(account_cid) => {
+                let record = network.get_account(account_cid).await?;
+                crate::account::verify(&record)?;
+                if crate::account::account_id(&record)? == author {
+                    account = Some(record);
+                }
+            }
+            AccountMessage::Head(node_id) => {
+                if let Some(source) = message.source {
+                    heads.push((source, node_id));
+                }
+            }
+        }
+
+        if let Some(account) = account.as_ref() {
+            let delegated = heads
+                .iter()
+                .filter(|(source, _)| crate::account::is_delegated(account, source))
+                .map(|(_, head)| *head)
+                .collect::<Vec<_>>();
+            if !delegated.is_empty() {
+                return Ok(delegated);
+            }
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Whether `node_id` is in the history of one of `heads`
+async fn is_in_timeline(network: &Network, heads: &[cid::Cid], node_id: cid::Cid) -> bool {
+    for head in heads {
+        let check = crate::timeline::contains(network, *head, node_id, true);
+        if let Ok(Ok(true)) = tokio::time::timeout(CHECK_TIMEOUT, check).await {
+            return true;
+        }
+    }
+    false
+}
+
+/// Drops announcements we have seen before and peers that announce too much
+struct AnnouncementFilter {
+    seen: HashSet<Vec<u8>>,
+    seen_order: VecDeque<Vec<u8>>,
+    received: HashMap<PeerId, VecDeque<Instant>>,
+
+    /// When senders that went quiet were last forgotten
+    swept: Option<Instant>,
+}
+
+impl AnnouncementFilter {
+    fn new() -> Self {
+        AnnouncementFilter {
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            received: HashMap::new(),
+            swept: None,
+        }
+    }
+
+    fn admits(&mut self, source: PeerId, data: &[u8], now: Instant) -> bool {
+        self.forget_quiet_senders(now);
+
+        let received = self.received.entry(source).or_default();
+        while received
+            .front()
+            .map(|t| now.duration_since(*t) > RATE_LIMIT_WINDOW)
+            .unwrap_or(false)
+        {
+            received.pop_front();
+        }
+
+        if received.len() >= RATE_LIMIT {
+            tracing::debug!(%source, "Dropping announcement over rate limit");
+            return false;
+        }
+        received.push_back(now);
+
+        self.mark_seen(data)
+    }
+
+    /// Forget senders that sent nothing within `RATE_LIMIT_WINDOW`, at most once per window
+    fn forget_quiet_senders(&mut self, now: Instant) {
+        if let Some(swept) = self.swept {
+            if now.duration_since(swept) <= RATE_LIMIT_WINDOW {
+                return;
+            }
+        }
+        self.swept = Some(now);
+
+        self.received.retain(|_, received| {
+            received
+                .back()
+                .map(|t| now.duration_since(*t) <= RATE_LIMIT_WINDOW)
+                .unwrap_or(false)
+        });
+    }
+
+    /// Returns false if `data` was already seen
+    fn mark_seen(&mut self, data: &[u8]) -> bool {
+        if !self.seen.insert(data.to_vec()) {
+            return false;
+        }
+
+        self.seen_order.push_back(data.to_vec());
+        if self.seen_order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_drops_duplicates() {
+        let mut filter = AnnouncementFilter::new();
+        let now = Instant::now();
+
+        assert!(filter.admits(PeerId::random(), b"post", now));
+        assert!(!filter.admits(PeerId::random(), b"post", now));
+        assert!(filter.admits(PeerId::random(), b"other post", now));
+    }
+
+    #[test]
+    fn test_filter_rate_limits_sender() {
+        let mut filter = AnnouncementFilter::new();
+        let sender = PeerId::random();
+        let now = Instant::now();
+
+        for i in 0..RATE_LIMIT {
+            assert!(filter.admits(sender, &[i as u8], now));
+        }
+        assert!(!filter.admits(sender, b"too much", now));
+        assert!(filter.admits(PeerId::random(), b"too much", now));
+
+        let later = now + RATE_LIMIT_WINDOW + Duration::from_secs(1);
+        assert!(filter.admits(sender, b"later", later));
+
+        // Only the sender of the latest announcement is still tracked
+        assert_eq!(filter.received.len(), 1);
+        assert!(filter.received.contains_key(&sender));
+    }
+}
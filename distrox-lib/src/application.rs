@@ -1,122 +1,1744 @@
-use std::io::Cursor;
+use std::path::Path;
+use std::path::PathBuf;
 
 use distrox_types::{
+    account::{Account, AccountMessage},
+    announcement::Announcement,
     post::{OriginalPost, Post},
     util::{Mime, OffsetDateTime},
 };
+use libipld::prelude::Codec;
+use libp2p::identity::Keypair;
 use libp2p::Multiaddr;
 use tokio::sync::Mutex;
-use tracing::info;
+use tracing::{debug, info, warn};
 
 use crate::{
-    command::CommandReceiver, configuration::Configuration, error::Error, network::Network,
-    state::State,
+    account::{AccountSync, AccountSyncItem},
+    announce::{AnnounceItem, AnnounceSync},
+    command::{Command, CommandOutput, CommandReceiver, CommandResult, Peer},
+    configuration::{ConfigChanges, Configuration, NetworkOverrides, NetworkSettings},
+    error::Error,
+    event::{Discovery, Event, EventReceiver, EventSender},
+    follow::FollowSync,
+    metrics::{AppMetrics, MetricsReport, NetworkMetrics},
+    network::{CarImport, Network, StorageUsage},
+    pinning::{FetchedTimeline, PinningPolicy, TimelineFetches},
+    state::{OutboxEntry, State},
+    timeline::{TimelineCursor, TimelineEntry},
 };
 
+/// How many events are buffered for slow frontends, before they miss some
+const EVENT_CAPACITY: usize = 256;
+
+/// How often a metrics event is sent
+const METRICS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long after a new connection the outbox is flushed, so the peer can tell us its topics
+const OUTBOX_CONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How often publishing the outbox is retried without new connections
+const OUTBOX_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How often the currently connected peers are recorded in the peer book
+const PEER_BOOK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How many peers from the peer book are dialed on startup
+const RECONNECT_PEERS: usize = 16;
+
+/// How often we announce ourselves as provider of the timelines we hold, and look for providers
+/// of followed authors that are offline
+const DHT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// How long a single DHT lookup may take
+const DHT_LOOKUP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How many providers of an offline author are dialed
+const PROVIDERS_PER_AUTHOR: usize = 4;
+
+/// How long to look for our head in the history of the head of another device
+const MERGE_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
 pub struct Application {
     app_state: Mutex<AppState>,
 
     network: Network,
+    events: EventSender,
+    metrics: AppMetrics,
+}
+
+/// Options for starting the application
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Do not listen on any address or connect to any peer
+    pub offline: bool,
+
+    /// Settings to use instead of those of the configuration file
+    pub network: NetworkOverrides,
+}
+
+/// The files of an installation, as found or created by `Application::init_xdg`
+#[derive(Debug, Clone)]
+pub struct Setup {
+    pub config_path: PathBuf,
+    pub state_path: PathBuf,
+    pub device_key_path: PathBuf,
+    pub device_id: libp2p::PeerId,
+
+    /// The files that did not exist yet and were created
+    pub created: Vec<PathBuf>,
 }
 
 impl Application {
-    pub async fn load_from_xdg(xdg: xdg::BaseDirectories) -> Result<Self, Error> {
-        let (config, state) = tokio::try_join!(
-            Configuration::load_from_path(xdg.get_config_file("config.toml")),
-            State::load_from_path(xdg.get_state_file("state.toml")),
+    /// Create the directories, configuration, state and device key that do not exist yet
+    ///
+    /// A fresh configuration keeps the blockstore in the XDG data directory, has no bootstrap
+    /// nodes and listens on a port chosen by the system.
+    pub async fn init_xdg(xdg: &xdg::BaseDirectories) -> Result<Setup, Error> {
+        let config_path = xdg
+            .place_config_file("config.toml")
+            .map_err(Error::CreatingDirectory)?;
+        let state_path = xdg
+            .place_state_file("state.toml")
+            .map_err(Error::CreatingDirectory)?;
+        let device_key_path = xdg
+            .place_data_file("device.key")
+            .map_err(Error::CreatingDirectory)?;
+
+        let mut created = Vec::new();
+        if !tokio::fs::try_exists(&config_path).await.unwrap_or(false) {
+            let storage_path = xdg
+                .create_data_directory("blockstore")
+                .map_err(Error::CreatingDirectory)?;
+            Configuration::with_defaults(config_path.clone(), storage_path)
+                .save()
+                .await?;
+            created.push(config_path.clone());
+        }
+
+        if !tokio::fs::try_exists(&state_path).await.unwrap_or(false) {
+            State::empty(state_path.clone()).save().await?;
+            created.push(state_path.clone());
+        }
+
+        if !tokio::fs::try_exists(&device_key_path)
+            .await
+            .unwrap_or(false)
+        {
+            created.push(device_key_path.clone());
+        }
+        let device_key = crate::identity::load_or_generate_keypair(&device_key_path).await?;
+
+        for path in created.iter() {
+            info!(path = %path.display(), "Created");
+        }
+
+        Ok(Setup {
+            config_path,
+            state_path,
+            device_key_path,
+            device_id: device_key.public().to_peer_id(),
+            created,
+        })
+    }
+
+    /// Load the application from the XDG directories, setting them up first if necessary
+    pub async fn load_from_xdg(xdg: xdg::BaseDirectories, options: Options) -> Result<Self, Error> {
+        let setup = Self::init_xdg(&xdg).await?;
+        let (mut config, state) = tokio::try_join!(
+            Configuration::load_from_path(setup.config_path),
+            State::load_from_path(setup.state_path),
         )?;
+        config.override_network(options.network);
+
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(Error::InvalidConfiguration(problems));
+        }
+
+        let device_key = crate::identity::load_keypair(&setup.device_key_path).await?;
+
+        let account_key_path = xdg.get_data_file("account.key");
+        let account_key = if tokio::fs::try_exists(&account_key_path)
+            .await
+            .unwrap_or(false)
+        {
+            Some(crate::identity::load_keypair(&account_key_path).await?)
+        } else {
+            None
+        };
 
         let network = {
             let storage_path = config.network().storage_path().to_path_buf();
-            let bootstrap = crate::network::BootstrapNodes(
-                config
-                    .network()
-                    .bootstrap_nodes()
-                    .iter()
-                    .cloned()
-                    .map(|n| n.try_into())
-                    .collect::<Result<Vec<_>, Error>>()?,
-            );
-            let listening = crate::network::ListeningAddrs(
-                config
-                    .network()
-                    .listening_addrs()
-                    .iter()
-                    .cloned()
-                    .map(|n| n.try_into())
-                    .collect::<Result<Vec<_>, Error>>()?,
-            );
+            let bootstrap =
+                crate::network::BootstrapNodes(multiaddrs(config.network().bootstrap_nodes())?);
+            let listening =
+                crate::network::ListeningAddrs(multiaddrs(config.network().listening_addrs())?);
 
-            Network::load(storage_path, bootstrap, listening).await?
+            Network::load(
+                storage_path,
+                bootstrap,
+                listening,
+                config.network().discovery(),
+                device_key,
+                options.offline,
+            )
+            .await?
         };
 
-        let app_state = Mutex::new(AppState { config, state });
-        Ok(Application { app_state, network })
+        let app_state = Mutex::new(AppState {
+            config,
+            state,
+            account_key_path,
+            account_key,
+        });
+        let (events, _) = tokio::sync::broadcast::channel(EVENT_CAPACITY);
+        Ok(Application {
+            app_state,
+            network,
+            events,
+            metrics: AppMetrics::default(),
+        })
+    }
+
+    /// Receive the events of the backend, starting now
+    pub fn events(&self) -> EventReceiver {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: Event) {
+        // Having no frontend listening is fine
+        let _ = self.events.send(event);
     }
 
     pub async fn run(&self, mut receiver: CommandReceiver) -> Result<(), Error> {
-        while let Some(command) = receiver.recv().await {
-            match command {
-                crate::command::Command::QuitApp => return Ok(()),
-                crate::command::Command::PostText { text } => {
-                    let latest_post = self.app_state.lock().await.get_latest_post()?;
-
-                    let content_id = self
-                        .network
-                        .insert_blob(futures::stream::iter(text.bytes()))
-                        .await?;
+        let mut account_sync = self.resume_account_sync().await?;
+        let mut follow_sync = self.resume_follow_sync().await?;
+        let mut announce_sync = self.start_announce_sync().await?;
+        let (mut timeline_fetches, mut fetched_timelines) = TimelineFetches::new();
+        let mut connection_events = self.network.connection_events();
+        let (reachable_sender, mut reachable_authors) = tokio::sync::mpsc::unbounded_channel();
+        let mut dht_refresh: Option<BackgroundTask> = None;
+        let (metrics_report, metrics_snapshot) =
+            tokio::sync::watch::channel(MetricsReport::default());
+        let _metrics_endpoint = self.start_metrics_endpoint(metrics_snapshot).await?;
+        self.enforce_quota().await?;
+        self.reconnect_known_peers().await?;
+
+        let outbox_flush = tokio::time::sleep(std::time::Duration::ZERO);
+        tokio::pin!(outbox_flush);
+        let mut peer_book_update = tokio::time::interval(PEER_BOOK_INTERVAL);
+        let mut dht_update = tokio::time::interval(DHT_INTERVAL);
+        let mut metrics_update = tokio::time::interval(METRICS_INTERVAL);
+
+        loop {
+            tokio::select! {
+                request = receiver.recv() => match request {
+                    None => return Ok(()),
+                    Some(request) if matches!(request.command, Command::QuitApp) => {
+                        request.respond(Ok(CommandOutput::Done));
+                        return Ok(());
+                    }
+                    Some(request) => {
+                        debug!(command = ?request.command, "Handling command");
+                        let result = self
+                            .handle_command(
+                                &request.command,
+                                &mut account_sync,
+                                &mut follow_sync,
+                                &mut announce_sync,
+                            )
+                            .await;
+                        info!(command = ?request.command, ?result, "Command finished");
+                        request.respond(result);
+                    }
+                },
 
-                    let new_post = Post::Original({
-                        OriginalPost {
-                            content: content_id,
-                            content_mime: Mime(mime::TEXT_PLAIN_UTF_8),
-                            timestamp: OffsetDateTime(time::OffsetDateTime::now_utc()),
+                Some(item) = crate::account::next_sync_item(&mut account_sync) => {
+                    if let Err(error) = self.handle_account_sync_item(item, &mut account_sync).await {
+                        warn!(?error, "Failed to handle account synchronization");
+                    }
+                }
+
+                Some((author, message)) = follow_sync.next() => {
+                    let result = self
+                        .handle_followed_message(author, message, &mut timeline_fetches)
+                        .await;
+                    if let Err(error) = result {
+                        warn!(%author, ?error, "Failed to handle message of followed author");
+                    }
+                }
+
+                Some(item) = crate::announce::next_announcement(&mut announce_sync) => match item {
+                    AnnounceItem::Received(source, message) => {
+                        let result = self
+                            .handle_announcement(&account_sync, &mut announce_sync, source, message)
+                            .await;
+                        if let Err(error) = result {
+                            debug!(%source, ?error, "Failed to handle announcement");
                         }
-                    });
+                    }
+                    AnnounceItem::Verified(discovery) => {
+                        self.emit(Event::Discovered(Box::new(discovery)));
+                    }
+                },
+
+                Ok(event) = connection_events.recv() => {
+                    let flush_at = tokio::time::Instant::now() + OUTBOX_CONNECT_DELAY;
+                    if matches!(event, Event::ConnectionEstablished { .. })
+                        && outbox_flush.deadline() > flush_at
+                    {
+                        outbox_flush.as_mut().reset(flush_at);
+                    }
+                    self.emit(event);
+                }
+
+                () = &mut outbox_flush => {
+                    if let Err(error) = self.flush_outbox().await {
+                        warn!(?error, "Failed to flush outbox");
+                    }
+                    let retry_at = tokio::time::Instant::now() + OUTBOX_RETRY_INTERVAL;
+                    outbox_flush.as_mut().reset(retry_at);
+                }
+
+                _ = peer_book_update.tick() => {
+                    if let Err(error) = self.record_connected_peers().await {
+                        warn!(?error, "Failed to update peer book");
+                    }
+                }
+
+                _ = dht_update.tick() => {
+                    if dht_refresh.as_ref().map_or(true, |task| task.0.is_finished()) {
+                        match self.start_dht_refresh(&account_sync, reachable_sender.clone()).await {
+                            Ok(task) => dht_refresh = task,
+                            Err(error) => warn!(?error, "Failed to start DHT refresh"),
+                        }
+                    }
+                }
+
+                Some(author) = reachable_authors.recv() => {
+                    self.fetch_reachable_author(author, &mut timeline_fetches).await;
+                }
+
+                Some(fetched) = fetched_timelines.recv() => {
+                    timeline_fetches.finished(&fetched);
+                    let author = fetched.author.clone();
+                    if let Err(error) = self.handle_fetched_timeline(fetched).await {
+                        warn!(%author, ?error, "Failed to fetch timeline of followed author");
+                    }
+                }
+
+                _ = metrics_update.tick() => {
+                    let report = self.metrics_report(receiver.queue_depth()).await;
+                    if self.events.receiver_count() > 0 {
+                        self.emit(Event::Metrics(Box::new(report.network.clone())));
+                    }
+                    metrics_report.send_replace(report);
+                }
+            }
+        }
+    }
+
+    async fn handle_command(
+        &self,
+        command: &Command,
+        account_sync: &mut Option<AccountSync>,
+        follow_sync: &mut FollowSync,
+        announce_sync: &mut Option<AnnounceSync>,
+    ) -> CommandResult {
+        match command {
+            Command::QuitApp => Ok(CommandOutput::Done),
+            Command::PostText { text } => {
+                let content_id = self
+                    .network
+                    .insert_blob(futures::stream::iter(text.clone().into_bytes()))
+                    .await?;
+
+                let new_post = Post::Original({
+                    OriginalPost {
+                        content: content_id,
+                        content_mime: Mime(mime::TEXT_PLAIN_UTF_8),
+                        timestamp: OffsetDateTime(time::OffsetDateTime::now_utc()),
+                    }
+                });
+
+                let node_id = self.append_post(account_sync, new_post).await?;
+                Ok(CommandOutput::Node {
+                    node_id: node_id.to_string(),
+                })
+            }
+
+            Command::ConnectTo { uri } => {
+                let multiaddr: Multiaddr = uri.parse().map_err(|source| Error::ParseMultiAddr {
+                    addr: uri.to_string(),
+                    source,
+                })?;
+                self.network.connect_without_peer(multiaddr).await?;
+                self.record_connected_peers().await?;
+                Ok(CommandOutput::Done)
+            }
+
+            Command::CreateAccount => {
+                self.create_account(account_sync).await?;
+                self.account_output(account_sync).await
+            }
+
+            Command::AddDevice { peer_id } => {
+                self.add_device(account_sync, peer_id).await?;
+                self.account_output(account_sync).await
+            }
+
+            Command::JoinAccount { account } => {
+                self.join_account(account_sync, account).await?;
+                self.account_output(account_sync).await
+            }
+
+            Command::Follow { author } => {
+                self.follow(account_sync, follow_sync, announce_sync, author)
+                    .await?;
+                Ok(CommandOutput::Done)
+            }
+
+            Command::Unfollow { author } => {
+                self.unfollow(follow_sync, author).await?;
+                Ok(CommandOutput::Done)
+            }
+
+            Command::CollectGarbage => {
+                let report = self.network.gc().await?;
+                Ok(CommandOutput::GarbageCollected {
+                    removed_blocks: report.removed_blocks,
+                    freed_bytes: report.freed_bytes,
+                })
+            }
+
+            Command::AddBootstrapNode { addr } => {
+                self.add_bootstrap_node(addr).await?;
+                Ok(CommandOutput::Done)
+            }
+
+            Command::RemoveBootstrapNode { addr } => {
+                self.remove_bootstrap_node(addr).await?;
+                Ok(CommandOutput::Done)
+            }
+
+            Command::Announce {
+                author,
+                node_id,
+                community,
+            } => {
+                let node_id = self
+                    .announce(
+                        account_sync,
+                        announce_sync,
+                        author,
+                        node_id,
+                        community.clone(),
+                    )
+                    .await?;
+                Ok(CommandOutput::Node {
+                    node_id: node_id.to_string(),
+                })
+            }
+
+            Command::Timeline { limit, after } => {
+                let (entries, next) = self.timeline(account_sync, *limit, after.as_ref()).await?;
+                Ok(CommandOutput::Timeline { entries, next })
+            }
+
+            Command::ExportCar { head, path } => {
+                let head = head.as_deref().map(cid::Cid::try_from).transpose()?;
+                let blocks = self.export_car(head, path).await?;
+                Ok(CommandOutput::CarExported {
+                    path: path.clone(),
+                    blocks,
+                })
+            }
+
+            Command::ImportCar { path } => {
+                let import = self.import_car(path).await?;
+                Ok(CommandOutput::CarImported {
+                    path: path.clone(),
+                    roots: import.roots.iter().map(ToString::to_string).collect(),
+                    blocks: import.blocks,
+                })
+            }
+
+            Command::Peers => {
+                let peers = self
+                    .connected_peer_addrs()
+                    .await?
+                    .into_iter()
+                    .map(|(peer_id, addrs)| Peer { peer_id, addrs })
+                    .collect();
+                Ok(CommandOutput::Peers { peers })
+            }
+
+            Command::ReloadConfig => Ok(CommandOutput::ConfigReloaded(self.reload_config().await?)),
+
+            Command::NetworkSettings => {
+                let file = self.app_state.lock().await.config.reread().await?;
+                Ok(CommandOutput::NetworkSettings(file.network_settings()))
+            }
+
+            Command::SetNetworkSettings { settings } => {
+                self.save_network_settings(settings.clone()).await?;
+                Ok(CommandOutput::ConfigReloaded(self.reload_config().await?))
+            }
+
+            Command::Whoami => {
+                let account_record = self.app_state.lock().await.get_account();
+                let account_id = account_sync
+                    .as_ref()
+                    .map(|sync| crate::account::account_id(&sync.account))
+                    .transpose()?;
+
+                Ok(CommandOutput::Identity {
+                    device_id: self.network.local_peer_id()?.to_string(),
+                    account_id: account_id.map(|id| id.to_string()),
+                    account_record: account_record.map(|cid| cid.to_string()),
+                })
+            }
+        }
+    }
+
+    /// Our own timeline and those of followed authors, merged into one feed
+    async fn timeline(
+        &self,
+        account_sync: &Option<AccountSync>,
+        limit: usize,
+        after: Option<&TimelineCursor>,
+    ) -> Result<(Vec<TimelineEntry>, Option<TimelineCursor>), Error> {
+        let own_author = match account_sync.as_ref() {
+            Some(sync) => crate::account::account_id(&sync.account)?,
+            None => self.network.local_peer_id()?,
+        };
+
+        let heads = {
+            let app_state = self.app_state.lock().await;
+            let own_head = app_state
+                .get_latest_post()
+                .map(|head| (own_author.to_string(), head));
 
-                    let post_id = self.network.insert_post(new_post).await?;
+            let followed_heads = app_state
+                .state
+                .follows()
+                .iter()
+                .filter_map(|follow| {
+                    follow
+                        .head()
+                        .map(|head| (follow.author().to_string(), head))
+                })
+                .collect::<Vec<_>>();
 
-                    let new_node = distrox_types::node::Node {
-                        protocol_version: distrox_types::protocol::ProtocolVersion(0),
-                        parents: latest_post.into_iter().collect(),
-                        post: Some(post_id),
-                    };
+            own_head
+                .into_iter()
+                .chain(followed_heads)
+                .collect::<Vec<_>>()
+        };
+
+        let cursor = match after {
+            Some(cursor) => cursor.clone(),
+            None => TimelineCursor::from_heads(&heads),
+        };
+        crate::timeline::page(&self.network, &heads, &cursor, limit).await
+    }
+
+    async fn account_output(&self, account_sync: &Option<AccountSync>) -> CommandResult {
+        let sync = account_sync.as_ref().ok_or(Error::NoAccount)?;
+        let account_record = self
+            .app_state
+            .lock()
+            .await
+            .get_account()
+            .ok_or(Error::NoAccount)?;
+
+        Ok(CommandOutput::Account {
+            account_id: crate::account::account_id(&sync.account)?.to_string(),
+            account_record: account_record.to_string(),
+        })
+    }
+
+    /// Add a post to our timeline and tell our other devices and followers about it
+    async fn append_post(
+        &self,
+        account_sync: &Option<AccountSync>,
+        post: Post,
+    ) -> Result<cid::Cid, Error> {
+        let (latest_post, pending_heads) = {
+            let app_state = self.app_state.lock().await;
+            (app_state.get_latest_post(), app_state.get_pending_heads())
+        };
+
+        let post_id = self.network.insert_post(post).await?;
+
+        let new_node = distrox_types::node::Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents: latest_post.into_iter().chain(pending_heads).collect(),
+            post: Some(post_id),
+        };
+
+        let node_id = self.network.insert_node(new_node).await?;
+
+        self.set_own_head(&mut *self.app_state.lock().await, node_id)
+            .await?;
+        self.publish_account_message(account_sync, AccountMessage::Head(node_id))
+            .await;
+        self.metrics.post_published();
+        self.enforce_quota().await?;
+        Ok(node_id)
+    }
+
+    pub async fn metrics(&self) -> NetworkMetrics {
+        self.network.metrics().await
+    }
+
+    async fn metrics_report(&self, command_queue_depth: usize) -> MetricsReport {
+        let mut report = MetricsReport {
+            network: self.network.metrics().await,
+            blockstore_bytes: self.network.blockstore_size(),
+            command_queue_depth,
+            ..Default::default()
+        };
+        self.metrics.fill_report(&mut report);
+        report
+    }
+
+    /// Serve the reports sent to `report` on the configured metrics address, if any
+    async fn start_metrics_endpoint(
+        &self,
+        report: tokio::sync::watch::Receiver<MetricsReport>,
+    ) -> Result<Option<BackgroundTask>, Error> {
+        let Some(addr) = self.app_state.lock().await.config.metrics().listen() else {
+            return Ok(None);
+        };
+
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(Error::MetricsEndpoint)?;
+        info!(%addr, "Serving metrics");
+        let task = tokio::spawn(crate::metrics::serve(listener, report));
+        Ok(Some(BackgroundTask(task)))
+    }
+
+    /// How much disk space the blockstore uses, for our own content, content of followed authors
+    /// and cached data
+    pub async fn storage_usage(&self) -> Result<StorageUsage, Error> {
+        let own_roots = {
+            let app_state = self.app_state.lock().await;
+            app_state
+                .get_latest_post()
+                .into_iter()
+                .chain(app_state.get_pending_heads())
+                .chain(app_state.get_account())
+                .collect::<Vec<_>>()
+        };
+
+        self.network.storage_usage(&own_roots).await
+    }
+
+    /// Export everything reachable from `head` into a CAR file at `path`
+    ///
+    /// If no head is given, our own timeline is exported. Returns the number of exported blocks.
+    pub async fn export_car(&self, head: Option<cid::Cid>, path: &Path) -> Result<usize, Error> {
+        let head = match head {
+            Some(head) => head,
+            None => self
+                .app_state
+                .lock()
+                .await
+                .get_latest_post()
+                .ok_or(Error::NoTimeline)?,
+        };
+
+        let file = tokio::fs::File::create(path)
+            .await
+            .map_err(|source| Error::OpenCarFile {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        self.network
+            .export_car(&[head], tokio::io::BufWriter::new(file))
+            .await
+    }
+
+    /// Import all blocks of the CAR file at `path`
+    pub async fn import_car(&self, path: &Path) -> Result<CarImport, Error> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|source| Error::OpenCarFile {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        self.network
+            .import_car(tokio::io::BufReader::new(file))
+            .await
+    }
+
+    /// Evict unpinned data if the blockstore is larger than the configured quota
+    async fn enforce_quota(&self) -> Result<(), Error> {
+        let Some(quota) = self.app_state.lock().await.config.network().quota_bytes() else {
+            return Ok(());
+        };
+
+        let usage = self.storage_usage().await?;
+        if usage.total_bytes() <= quota {
+            return Ok(());
+        }
+
+        let report = self
+            .network
+            .evict_unpinned(usage.total_bytes() - quota)
+            .await?;
+        info!(?usage, ?report, quota, "Evicted data to stay within quota");
+
+        if usage.total_bytes() - report.freed_bytes > quota {
+            warn!(?usage, quota, "Pinned data exceeds storage quota");
+        }
+        Ok(())
+    }
+
+    async fn resume_account_sync(&self) -> Result<Option<AccountSync>, Error> {
+        let Some(account_cid) = self.app_state.lock().await.get_account() else {
+            return Ok(None);
+        };
+
+        let account = self.network.get_account(account_cid).await?;
+        crate::account::verify(&account)?;
+
+        let account_sync = Some(self.start_account_sync(account).await?);
+        self.publish_latest_head(&account_sync).await?;
+        Ok(account_sync)
+    }
+
+    async fn resume_follow_sync(&self) -> Result<FollowSync, Error> {
+        let mut follow_sync = FollowSync::new();
+        for follow in self.app_state.lock().await.state.follows() {
+            follow_sync
+                .subscribe(&self.network, parse_peer_id(follow.author())?)
+                .await?;
+        }
+        Ok(follow_sync)
+    }
+
+    async fn start_account_sync(&self, account: Account) -> Result<AccountSync, Error> {
+        let topic = crate::account::sync_topic(&crate::account::account_id(&account)?);
+        let messages = self.network.subscribe(topic.clone()).await?;
+        let events = self.network.subscription_events(&topic).await?;
 
-                    let node_id = self.network.insert_node(new_node).await?;
+        Ok(AccountSync {
+            account,
+            topic,
+            messages,
+            events,
+        })
+    }
+
+    async fn create_account(&self, account_sync: &mut Option<AccountSync>) -> Result<(), Error> {
+        let mut app_state = self.app_state.lock().await;
+        if account_sync.is_some() || app_state.get_account().is_some() {
+            return Err(Error::AccountExists);
+        }
+
+        let account_key = Keypair::generate_ed25519();
+        let account = crate::account::new_account(&account_key, self.network.local_peer_id()?)?;
+        crate::identity::store_keypair(&app_state.account_key_path, &account_key).await?;
+        app_state.account_key = Some(account_key);
+
+        let account_cid = self.network.insert_account(account.clone()).await?;
+        self.network.pin(account_cid, false).await?;
+        app_state.set_account(account_cid).await?;
+        drop(app_state);
+
+        info!(account_id = %crate::account::account_id(&account)?, %account_cid, "Account created");
+        *account_sync = Some(self.start_account_sync(account).await?);
+        Ok(())
+    }
+
+    async fn add_device(
+        &self,
+        account_sync: &mut Option<AccountSync>,
+        peer_id: &str,
+    ) -> Result<(), Error> {
+        let device = parse_peer_id(peer_id)?;
+
+        let sync = account_sync.as_mut().ok_or(Error::NoAccount)?;
+        let mut app_state = self.app_state.lock().await;
+        let account_key = app_state.account_key.as_ref().ok_or(Error::NoAccountKey)?;
+
+        let mut account = sync.account.clone();
+        crate::account::delegate(&mut account, account_key, device)?;
+
+        let account_cid = self.network.insert_account(account.clone()).await?;
+        self.replace_account_record(&mut app_state, account_cid)
+            .await?;
+        drop(app_state);
+
+        sync.account = account;
+        self.publish_account_message(account_sync, AccountMessage::Account(account_cid))
+            .await;
+        Ok(())
+    }
+
+    async fn join_account(
+        &self,
+        account_sync: &mut Option<AccountSync>,
+        account_cid: &str,
+    ) -> Result<(), Error> {
+        if account_sync.is_some() {
+            return Err(Error::AccountExists);
+        }
+
+        let account_cid = cid::Cid::try_from(account_cid)?;
+        let account = self.network.get_account(account_cid).await?;
+        crate::account::verify(&account)?;
+
+        if !crate::account::is_delegated(&account, &self.network.local_peer_id()?) {
+            return Err(Error::DeviceNotDelegated);
+        }
+
+        self.replace_account_record(&mut *self.app_state.lock().await, account_cid)
+            .await?;
+        *account_sync = Some(self.start_account_sync(account).await?);
+        self.publish_latest_head(account_sync).await
+    }
+
+    async fn handle_account_sync_item(
+        &self,
+        item: AccountSyncItem,
+        account_sync: &mut Option<AccountSync>,
+    ) -> Result<(), Error> {
+        match item {
+            // Someone joined the topic, tell them who we are and where our timeline is
+            AccountSyncItem::Event(rust_ipfs::PubsubEvent::Subscribe { .. }) => {
+                let account_cid = self.app_state.lock().await.get_account();
+                if let Some(account_cid) = account_cid {
+                    self.publish_account_message(
+                        account_sync,
+                        AccountMessage::Account(account_cid),
+                    )
+                    .await;
+                }
+                self.publish_latest_head(account_sync).await
+            }
+            AccountSyncItem::Event(rust_ipfs::PubsubEvent::Unsubscribe { .. }) => Ok(()),
+            AccountSyncItem::Message(message) => {
+                let Some(sync) = account_sync.as_mut() else {
+                    return Ok(());
+                };
+
+                let source = message.source.ok_or(Error::DeviceNotDelegated)?;
+                if source == self.network.local_peer_id()? {
+                    return Ok(());
+                }
 
-                    self.app_state.lock().await.set_latest_post(node_id).await?;
+                if !crate::account::is_delegated(&sync.account, &source) {
+                    return Err(Error::DeviceNotDelegated);
                 }
 
-                crate::command::Command::ConnectTo { uri } => {
-                    let multiaddr: Multiaddr = uri.parse().unwrap();
-                    info!(?uri, "Connecting");
-                    let result = self.network.connect_without_peer(multiaddr).await;
-                    info!(?uri, ?result, "Connecting finished");
+                match libipld::cbor::DagCborCodec.decode(&message.data)? {
+                    AccountMessage::Head(node_id) => self.merge_head(node_id).await,
+                    AccountMessage::Account(account_cid) => {
+                        let account = self.network.get_account(account_cid).await?;
+                        crate::account::verify(&account)?;
+
+                        if crate::account::account_id(&account)?
+                            != crate::account::account_id(&sync.account)?
+                        {
+                            return Err(Error::NotAccountKey);
+                        }
+
+                        self.replace_account_record(&mut *self.app_state.lock().await, account_cid)
+                            .await?;
+                        sync.account = account;
+                        Ok(())
+                    }
                 }
             }
         }
+    }
+
+    /// Merge the head of another device of our account into our timeline
+    ///
+    /// A head that is already part of our history is ignored. If the other head builds on ours,
+    /// we simply adopt it. Otherwise it is remembered and becomes a parent of our next node.
+    async fn merge_head(&self, node_id: cid::Cid) -> Result<(), Error> {
+        let (latest_post, pending_heads) = {
+            let app_state = self.app_state.lock().await;
+            (app_state.get_latest_post(), app_state.get_pending_heads())
+        };
+
+        if latest_post == Some(node_id) || pending_heads.contains(&node_id) {
+            return Ok(());
+        }
+
+        for head in latest_post.iter().chain(pending_heads.iter()) {
+            if crate::timeline::contains(&self.network, *head, node_id, false).await? {
+                debug!(%node_id, %head, "Ignoring stale head of other device");
+                return Ok(());
+            }
+        }
 
+        // Fetching the other history can take long, a head we cannot check in time is merged
+        let builds_on_ours = match latest_post {
+            None => true,
+            Some(latest_post) => {
+                let check = crate::timeline::contains(&self.network, node_id, latest_post, true);
+                match tokio::time::timeout(MERGE_CHECK_TIMEOUT, check).await {
+                    Ok(builds_on_ours) => builds_on_ours?,
+                    Err(_) => {
+                        debug!(%node_id, "Timed out checking head of other device");
+                        false
+                    }
+                }
+            }
+        };
+
+        let mut app_state = self.app_state.lock().await;
+        let unchanged =
+            app_state.get_latest_post() == latest_post && app_state.get_pending_heads().is_empty();
+
+        if builds_on_ours && unchanged {
+            info!(%node_id, "Fast-forwarding to head of other device");
+            self.set_own_head(&mut app_state, node_id).await
+        } else {
+            info!(%node_id, "Remembering head of other device for merging");
+            self.network.pin(node_id, true).await?;
+            app_state.add_pending_head(node_id).await
+        }
+    }
+
+    /// Make `node_id` the head of our timeline
+    ///
+    /// The new head is pinned recursively, which keeps our whole timeline, so the pin of the
+    /// previous head is not needed anymore.
+    async fn set_own_head(&self, app_state: &mut AppState, node_id: cid::Cid) -> Result<(), Error> {
+        let previous_heads = app_state
+            .get_latest_post()
+            .into_iter()
+            .chain(app_state.get_pending_heads())
+            .filter(|head| *head != node_id)
+            .collect::<Vec<_>>();
+
+        self.network.pin(node_id, true).await?;
+        app_state.set_latest_post(node_id).await?;
+        self.emit(Event::TimelineChanged { head: node_id });
+
+        for head in previous_heads {
+            self.network.unpin(head, true).await?;
+        }
+        Ok(())
+    }
+
+    async fn replace_account_record(
+        &self,
+        app_state: &mut AppState,
+        account_cid: cid::Cid,
+    ) -> Result<(), Error> {
+        let previous = app_state.get_account();
+        self.network.pin(account_cid, false).await?;
+        app_state.set_account(account_cid).await?;
+
+        if let Some(previous) = previous.filter(|previous| *previous != account_cid) {
+            self.network.unpin(previous, false).await?;
+        }
         Ok(())
     }
+
+    async fn follow(
+        &self,
+        account_sync: &Option<AccountSync>,
+        follow_sync: &mut FollowSync,
+        announce_sync: &mut Option<AnnounceSync>,
+        author: &str,
+    ) -> Result<(), Error> {
+        let author_id = parse_peer_id(author)?;
+        if let Some(sync) = account_sync.as_ref() {
+            if crate::account::account_id(&sync.account)? == author_id {
+                return Err(Error::FollowingOwnAccount);
+            }
+        }
+
+        // Verifying announcements listens on the topic of the author, which following takes over
+        if let Some(sync) = announce_sync.as_mut() {
+            sync.cancel_verification(&author_id).await;
+        }
+
+        let mut app_state = self.app_state.lock().await;
+        if app_state.state.follow(author).is_some() {
+            return Ok(());
+        }
+
+        app_state.state.add_follow(author.to_string()).await?;
+        follow_sync.subscribe(&self.network, author_id).await
+    }
+
+    async fn unfollow(&self, follow_sync: &mut FollowSync, author: &str) -> Result<(), Error> {
+        let author_id = parse_peer_id(author)?;
+        let Some(follow) = self.app_state.lock().await.state.remove_follow(author).await? else {
+            return Ok(());
+        };
+
+        follow_sync.unsubscribe(&self.network, author_id).await?;
+        self.metrics.forget_author(author);
+        let provider_block = crate::account::provider_block(&author_id)?;
+        self.network.stop_providing(*provider_block.cid()).await?;
+
+        if let Some(head) = follow.head() {
+            let others = self.kept_heads(author).await;
+            let policy = PinningPolicy::nothing();
+            crate::pinning::apply_to_timeline(&self.network, head, &policy, &others).await?;
+        }
+        if let Some(account) = follow.account() {
+            self.network.unpin(account, false).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_followed_message(
+        &self,
+        author: libp2p::PeerId,
+        message: libp2p::gossipsub::Message,
+        timeline_fetches: &mut TimelineFetches,
+    ) -> Result<(), Error> {
+        let author_key = author.to_string();
+
+        match libipld::cbor::DagCborCodec.decode(&message.data)? {
+            // The account record is self-certifying, so it does not matter who sent it
+            AccountMessage::Account(account_cid) => {
+                let account = self.network.get_account(account_cid).await?;
+                crate::account::verify(&account)?;
+                if crate::account::account_id(&account)? != author {
+                    return Err(Error::NotAccountKey);
+                }
+
+                let mut app_state = self.app_state.lock().await;
+                let previous = app_state.get_followed_account(&author_key);
+                self.network.pin(account_cid, false).await?;
+                app_state
+                    .state
+                    .store_followed_account(&author_key, account_cid)
+                    .await?;
+
+                if let Some(previous) = previous.filter(|previous| *previous != account_cid) {
+                    self.network.unpin(previous, false).await?;
+                }
+                Ok(())
+            }
+
+            AccountMessage::Head(node_id) => {
+                let source = message.source.ok_or(Error::DeviceNotDelegated)?;
+                let (account_cid, policy) = {
+                    let app_state = self.app_state.lock().await;
+                    let account_cid = app_state
+                        .get_followed_account(&author_key)
+                        .ok_or(Error::UnknownAccount)?;
+                    let policy = PinningPolicy::from(app_state.config.pinning());
+                    (account_cid, policy)
+                };
+
+                let account = self.network.get_account(account_cid).await?;
+                if !crate::account::is_delegated(&account, &source) {
+                    return Err(Error::DeviceNotDelegated);
+                }
+
+                self.app_state
+                    .lock()
+                    .await
+                    .state
+                    .store_followed_head(&author_key, node_id)
+                    .await?;
+
+                let others = self.kept_heads(&author_key).await;
+                timeline_fetches.start(&self.network, author_key, node_id, policy, others);
+                Ok(())
+            }
+        }
+    }
+
+    /// Take note of a followed timeline that was fetched in the background
+    async fn handle_fetched_timeline(&self, fetched: FetchedTimeline) -> Result<(), Error> {
+        let followed = self
+            .app_state
+            .lock()
+            .await
+            .state
+            .follow(&fetched.author)
+            .is_some();
+
+        if !followed {
+            // Unfollowed while fetching, remove the pins the fetch added
+            let others = self.kept_heads(&fetched.author).await;
+            let policy = PinningPolicy::nothing();
+            crate::pinning::apply_to_timeline(&self.network, fetched.head, &policy, &others)
+                .await?;
+            return Ok(());
+        }
+
+        let received = fetched.result?;
+        self.metrics.timeline_synced(&fetched.author, received);
+        self.emit(Event::TimelineChanged { head: fetched.head });
+        self.enforce_quota().await
+    }
+
+    async fn start_announce_sync(&self) -> Result<Option<AnnounceSync>, Error> {
+        if self.network.is_offline() {
+            return Ok(None);
+        }
+
+        let app_state = self.app_state.lock().await;
+        let communities = app_state.config.announce().communities();
+        AnnounceSync::subscribe(&self.network, communities)
+            .await
+            .map(Some)
+    }
+
+    async fn announce(
+        &self,
+        account_sync: &Option<AccountSync>,
+        announce_sync: &mut Option<AnnounceSync>,
+        author: &str,
+        node_id: &str,
+        community: Option<String>,
+    ) -> Result<cid::Cid, Error> {
+        let author = parse_peer_id(author)?;
+        let node_id = cid::Cid::try_from(node_id)?;
+        let post_id = self
+            .network
+            .get_node(node_id)
+            .await?
+            .post
+            .ok_or(Error::NodeWithoutPost { node_id })?;
+
+        let announce_node_id = self
+            .append_post(
+                account_sync,
+                Post::Announce(distrox_types::post::Announce { node_id, post_id }),
+            )
+            .await?;
+
+        let announcement = Announcement {
+            author: author.to_bytes(),
+            node_id,
+            post_id,
+        };
+        let data = libipld::cbor::DagCborCodec.encode(&announcement)?;
+        if let Some(sync) = announce_sync.as_mut() {
+            sync.mark_seen(&data);
+        }
+
+        let topic = match community {
+            Some(community) => crate::announce::community_topic(&community),
+            None => crate::announce::global_topic(),
+        };
+        self.publish(topic, &format!("announce/{post_id}"), data)
+            .await?;
+        Ok(announce_node_id)
+    }
+
+    /// Surface an announcement that passed deduplication and rate limiting as discovery, once it
+    /// is verified that the announced node is in the timeline of its author
+    ///
+    /// Timelines we hold are checked right away, other authors are verified in the background.
+    async fn handle_announcement(
+        &self,
+        account_sync: &Option<AccountSync>,
+        announce_sync: &mut Option<AnnounceSync>,
+        source: libp2p::PeerId,
+        message: libp2p::gossipsub::Message,
+    ) -> Result<(), Error> {
+        let announcement: Announcement = libipld::cbor::DagCborCodec.decode(&message.data)?;
+        let author = libp2p::PeerId::from_bytes(&announcement.author)
+            .map_err(|_| Error::ParsePeerId(format!("{:?}", announcement.author)))?;
+        let discovery = Discovery {
+            author,
+            announced_by: source,
+            node_id: announcement.node_id,
+            post_id: announcement.post_id,
+        };
+
+        let own_account = account_sync
+            .as_ref()
+            .map(|sync| crate::account::account_id(&sync.account))
+            .transpose()?;
+        let known_head = {
+            let app_state = self.app_state.lock().await;
+            if own_account == Some(author) {
+                Some(app_state.get_latest_post())
+            } else {
+                let author = author.to_string();
+                app_state.state.follow(&author).map(|follow| follow.head())
+            }
+        };
+
+        match known_head {
+            Some(head) => {
+                let node_id = discovery.node_id;
+                let contained = match head {
+                    Some(head) => {
+                        crate::timeline::contains(&self.network, head, node_id, false).await?
+                    }
+                    None => false,
+                };
+                if contained {
+                    self.emit(Event::Discovered(Box::new(discovery)));
+                } else {
+                    debug!(%author, %node_id, "Dropping announcement not in timeline of author");
+                }
+            }
+            None => {
+                if let Some(sync) = announce_sync.as_mut() {
+                    sync.verify(&self.network, discovery);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Provide the timelines we hold in the DHT and find providers for followed authors that
+    /// are offline, in the background
+    ///
+    /// Authors whose providers we connected to are sent to `reachable`.
+    async fn start_dht_refresh(
+        &self,
+        account_sync: &Option<AccountSync>,
+        reachable: tokio::sync::mpsc::UnboundedSender<String>,
+    ) -> Result<Option<BackgroundTask>, Error> {
+        if self.network.is_offline() {
+            return Ok(None);
+        }
+
+        let own_account = account_sync
+            .as_ref()
+            .map(|sync| crate::account::account_id(&sync.account))
+            .transpose()?;
+        let follows = self.app_state.lock().await.state.follows().to_vec();
+
+        let network = self.network.clone();
+        Ok(Some(BackgroundTask(tokio::spawn(async move {
+            if let Err(error) = refresh_dht(&network, own_account, follows, reachable).await {
+                warn!(?error, "Failed to refresh DHT");
+            }
+        }))))
+    }
+
+    /// Fetch the timeline of a followed author whose providers we just connected to
+    async fn fetch_reachable_author(&self, author: String, timeline_fetches: &mut TimelineFetches) {
+        let (head, policy) = {
+            let app_state = self.app_state.lock().await;
+            let head = app_state
+                .state
+                .follow(&author)
+                .and_then(|follow| follow.head());
+            (head, PinningPolicy::from(app_state.config.pinning()))
+        };
+
+        if let Some(head) = head {
+            let others = self.kept_heads(&author).await;
+            timeline_fetches.start(&self.network, author, head, policy, others);
+        }
+    }
+
+    /// The heads of our own timeline and of the followed timelines, except that of `author`
+    async fn kept_heads(&self, author: &str) -> Vec<cid::Cid> {
+        let app_state = self.app_state.lock().await;
+        let followed_heads = app_state
+            .state
+            .follows()
+            .iter()
+            .filter(|follow| follow.author() != author)
+            .filter_map(|follow| follow.head());
+
+        app_state
+            .get_latest_post()
+            .into_iter()
+            .chain(followed_heads)
+            .collect()
+    }
+
+    async fn publish_latest_head(&self, account_sync: &Option<AccountSync>) -> Result<(), Error> {
+        let latest_post = self.app_state.lock().await.get_latest_post();
+        if let Some(latest_post) = latest_post {
+            self.publish_account_message(account_sync, AccountMessage::Head(latest_post))
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn publish_account_message(
+        &self,
+        account_sync: &Option<AccountSync>,
+        message: AccountMessage,
+    ) {
+        let Some(sync) = account_sync.as_ref() else {
+            return;
+        };
+
+        let slot = match message {
+            AccountMessage::Head(_) => "head",
+            AccountMessage::Account(_) => "account",
+        };
+
+        let result = match libipld::cbor::DagCborCodec.encode(&message) {
+            Ok(data) => self.publish(sync.topic.clone(), slot, data).await,
+            Err(error) => Err(Error::from(error)),
+        };
+
+        if let Err(error) = result {
+            warn!(?error, ?message, "Failed to publish to account topic");
+        }
+    }
+
+    /// Publish on a topic, or keep the message in the outbox if that is not possible right now
+    async fn publish(&self, topic: String, slot: &str, data: Vec<u8>) -> Result<(), Error> {
+        match self.network.publish(topic.clone(), data.clone()).await {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                // Happens regularly while offline or if no peer is subscribed to the topic
+                debug!(?error, %topic, slot, "Publishing deferred");
+                let entry = OutboxEntry {
+                    topic,
+                    slot: slot.to_string(),
+                    data,
+                };
+                self.app_state
+                    .lock()
+                    .await
+                    .state
+                    .queue_outbox_entry(entry)
+                    .await
+            }
+        }
+    }
+
+    /// Dial the peers from the peer book that were seen most recently
+    async fn reconnect_known_peers(&self) -> Result<(), Error> {
+        if self.network.is_offline() {
+            return Ok(());
+        }
+
+        let mut known_peers = self.app_state.lock().await.state.peer_book().to_vec();
+        known_peers.sort_by_key(|peer| std::cmp::Reverse(peer.last_seen()));
+
+        let dials = known_peers
+            .into_iter()
+            .take(RECONNECT_PEERS)
+            .filter_map(|peer| {
+                let peer_id = parse_peer_id(peer.peer_id())
+                    .map_err(|error| warn!(?error, "Invalid peer in peer book"))
+                    .ok()?;
+                let addrs = peer
+                    .addrs()
+                    .iter()
+                    .filter_map(|addr| addr.parse::<Multiaddr>().ok())
+                    .collect::<Vec<_>>();
+                Some(async move {
+                    let result = self.network.connect(peer_id, addrs).await;
+                    debug!(%peer_id, ?result, "Reconnecting to known peer finished");
+                })
+            });
+
+        futures::future::join_all(dials).await;
+        self.record_connected_peers().await
+    }
+
+    /// The peers we are connected to, with their known addresses
+    async fn connected_peer_addrs(&self) -> Result<Vec<(String, Vec<String>)>, Error> {
+        let connected = self.network.connected_peers().await?;
+        if connected.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .network
+            .addrs()
+            .await?
+            .into_iter()
+            .filter(|(peer_id, _)| connected.contains(peer_id))
+            .map(|(peer_id, addrs)| {
+                let addrs = addrs.iter().map(ToString::to_string).collect();
+                (peer_id.to_string(), addrs)
+            })
+            .collect())
+    }
+
+    /// Store the currently connected peers and their addresses in the peer book
+    async fn record_connected_peers(&self) -> Result<(), Error> {
+        let peers = self.connected_peer_addrs().await?;
+        if peers.is_empty() {
+            return Ok(());
+        }
+
+        self.app_state
+            .lock()
+            .await
+            .state
+            .record_peers(peers, time::OffsetDateTime::now_utc())
+            .await
+    }
+
+    async fn add_bootstrap_node(&self, addr: &str) -> Result<(), Error> {
+        let multiaddr: Multiaddr = addr.parse().map_err(|source| Error::ParseMultiAddr {
+            addr: addr.to_string(),
+            source,
+        })?;
+
+        {
+            let mut app_state = self.app_state.lock().await;
+            let mut file = app_state.config.reread().await?;
+            if file
+                .network_mut()
+                .add_bootstrap_node(multiaddr.clone().into())
+            {
+                file.save().await?;
+            }
+            app_state
+                .config
+                .network_mut()
+                .add_bootstrap_node(multiaddr.clone().into());
+        }
+
+        self.network.add_bootstrap_node(multiaddr.clone()).await?;
+        if !self.network.is_offline() {
+            self.network.connect_without_peer(multiaddr).await?;
+            self.record_connected_peers().await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_bootstrap_node(&self, addr: &str) -> Result<(), Error> {
+        let multiaddr: Multiaddr = addr.parse().map_err(|source| Error::ParseMultiAddr {
+            addr: addr.to_string(),
+            source,
+        })?;
+
+        {
+            let mut app_state = self.app_state.lock().await;
+            let mut file = app_state.config.reread().await?;
+            if file
+                .network_mut()
+                .remove_bootstrap_node(&multiaddr.clone().into())
+            {
+                file.save().await?;
+            }
+            app_state
+                .config
+                .network_mut()
+                .remove_bootstrap_node(&multiaddr.clone().into());
+        }
+
+        self.network.remove_bootstrap_node(multiaddr).await
+    }
+
+    /// Read the configuration file again, apply the changes that do not need a restart and tell
+    /// the frontends about them
+    ///
+    /// Only the settings that were applied are taken over into the configuration in effect. The
+    /// others keep being reported as needing a restart, and a failed apply is retried on the next
+    /// reload.
+    ///
+    /// Pinning policies and the quota are read when they are used, so they only need the new
+    /// configuration in place. Bootstrap nodes and listening addresses are changed on the running
+    /// node.
+    async fn reload_config(&self) -> Result<ConfigChanges, Error> {
+        let (running, file) = {
+            let app_state = self.app_state.lock().await;
+            let file = app_state.config.reload().await?;
+            (app_state.config.clone(), file)
+        };
+
+        let mut changes = ConfigChanges::default();
+        for key in running.changed_keys(&file) {
+            let result = match key {
+                "network.bootstrap_nodes" => {
+                    self.apply_bootstrap_nodes(running.network(), file.network())
+                        .await
+                }
+                "network.listening_addrs" => {
+                    self.apply_listening_addrs(running.network(), file.network())
+                        .await
+                }
+                "network.quota_bytes" => self.apply_quota(&file).await,
+                "pinning" => Ok(true),
+                _ => Ok(false),
+            };
+
+            match result {
+                Ok(true) => {
+                    self.app_state.lock().await.config.take_key(key, &file);
+                    changes.applied.push(key.to_string());
+                }
+                Ok(false) => changes.restart_required.push(key.to_string()),
+                Err(error) => {
+                    warn!(key, ?error, "Failed to apply configuration change");
+                    changes.restart_required.push(key.to_string());
+                }
+            }
+        }
+
+        info!(?changes, "Reloaded configuration");
+        self.emit(Event::ConfigReloaded(changes.clone()));
+        Ok(changes)
+    }
+
+    /// Write new `[network]` settings to the configuration file, if there is nothing wrong with them
+    async fn save_network_settings(&self, settings: NetworkSettings) -> Result<(), Error> {
+        let mut config = self.app_state.lock().await.config.reread().await?;
+        config.set_network_settings(settings);
+
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(Error::InvalidConfiguration(problems));
+        }
+        config.save().await
+    }
+
+    async fn apply_bootstrap_nodes(
+        &self,
+        old: &crate::configuration::Network,
+        new: &crate::configuration::Network,
+    ) -> Result<bool, Error> {
+        let old = multiaddrs(old.bootstrap_nodes())?;
+        let new = multiaddrs(new.bootstrap_nodes())?;
+
+        for addr in old.iter().filter(|addr| !new.contains(addr)) {
+            self.network.remove_bootstrap_node(addr.clone()).await?;
+        }
+
+        for addr in new.iter().filter(|addr| !old.contains(addr)) {
+            self.network.add_bootstrap_node(addr.clone()).await?;
+            if !self.network.is_offline() {
+                if let Err(error) = self.network.connect_without_peer(addr.clone()).await {
+                    warn!(%addr, ?error, "Failed to connect to new bootstrap node");
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Listen on the new addresses and stop listening on the old ones
+    ///
+    /// Returns false if the node could not follow the change, e.g. because an address with an
+    /// ephemeral port cannot be removed again, so that it only applies after a restart.
+    async fn apply_listening_addrs(
+        &self,
+        old: &crate::configuration::Network,
+        new: &crate::configuration::Network,
+    ) -> Result<bool, Error> {
+        let old = multiaddrs(old.listening_addrs())?;
+        let new = multiaddrs(new.listening_addrs())?;
+        if self.network.is_offline() {
+            return Ok(true);
+        }
+
+        let mut applied = true;
+        for addr in old.iter().filter(|addr| !new.contains(addr)) {
+            if let Err(error) = self.network.remove_listening_address(addr.clone()).await {
+                warn!(%addr, ?error, "Failed to stop listening");
+                applied = false;
+            }
+        }
+
+        for addr in new.iter().filter(|addr| !old.contains(addr)) {
+            match self.network.add_listening_address(addr.clone()).await {
+                Ok(listening) => info!(%listening, "Listening on new address"),
+                Err(error) => {
+                    warn!(%addr, ?error, "Failed to listen");
+                    applied = false;
+                }
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Put the quota of `config` in effect and enforce it, going back to the old quota if that fails
+    async fn apply_quota(&self, config: &Configuration) -> Result<bool, Error> {
+        let old = {
+            let mut app_state = self.app_state.lock().await;
+            let old = app_state.config.clone();
+            app_state.config.take_key("network.quota_bytes", config);
+            old
+        };
+
+        if let Err(error) = self.enforce_quota().await {
+            let mut app_state = self.app_state.lock().await;
+            app_state.config.take_key("network.quota_bytes", &old);
+            return Err(error);
+        }
+        Ok(true)
+    }
+
+    /// Try to publish everything in the outbox, if we are connected to anyone
+    async fn flush_outbox(&self) -> Result<(), Error> {
+        let outbox = self.app_state.lock().await.state.outbox().to_vec();
+        if outbox.is_empty() || self.network.is_offline() {
+            return Ok(());
+        }
+
+        if self.network.connected_peers().await?.is_empty() {
+            return Ok(());
+        }
+
+        for entry in outbox {
+            match self
+                .network
+                .publish(entry.topic.clone(), entry.data.clone())
+                .await
+            {
+                Ok(()) => {
+                    info!(topic = %entry.topic, slot = %entry.slot, "Published from outbox");
+                    self.app_state
+                        .lock()
+                        .await
+                        .state
+                        .remove_outbox_entry(&entry)
+                        .await?;
+                }
+                Err(error) => {
+                    debug!(?error, topic = %entry.topic, "Outbox entry still not publishable");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A task of the main loop, which is cancelled when the main loop ends
+struct BackgroundTask(tokio::task::JoinHandle<()>);
+
+impl Drop for BackgroundTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn refresh_dht(
+    network: &Network,
+    own_account: Option<libp2p::PeerId>,
+    follows: Vec<crate::state::Follow>,
+    reachable: tokio::sync::mpsc::UnboundedSender<String>,
+) -> Result<(), Error> {
+    if network.connected_peers().await?.is_empty() {
+        return Ok(());
+    }
+
+    let followed_with_content = follows
+        .iter()
+        .filter(|follow| follow.head().is_some())
+        .map(|follow| parse_peer_id(follow.author()))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    for account_id in own_account.into_iter().chain(followed_with_content) {
+        let provider_block = crate::account::provider_block(&account_id)?;
+        if let Err(error) = network.provide(provider_block).await {
+            debug!(%account_id, ?error, "Failed to provide timeline");
+        }
+    }
+
+    for follow in follows {
+        match connect_to_providers(network, &follow).await {
+            Ok(true) => {
+                let _ = reachable.send(follow.author().to_string());
+            }
+            Ok(false) => {}
+            Err(error) => debug!(author = %follow.author(), ?error, "Failed to reach providers"),
+        }
+    }
+    Ok(())
+}
+
+/// If no device of a followed author is connected, connect to other peers holding their
+/// timeline
+///
+/// Returns whether we connected to any of them.
+async fn connect_to_providers(
+    network: &Network,
+    follow: &crate::state::Follow,
+) -> Result<bool, Error> {
+    let author_id = parse_peer_id(follow.author())?;
+    let connected = network.connected_peers().await?;
+
+    if let Some(account_cid) = follow.account() {
+        let account = network.get_account(account_cid).await?;
+        if connected
+            .iter()
+            .any(|peer_id| crate::account::is_delegated(&account, peer_id))
+        {
+            return Ok(false);
+        }
+    }
+
+    let local_peer_id = network.local_peer_id()?;
+    let provider_block = crate::account::provider_block(&author_id)?;
+    let providers = network
+        .find_providers(*provider_block.cid(), DHT_LOOKUP_TIMEOUT)
+        .await?
+        .into_iter()
+        .filter(|peer_id| *peer_id != local_peer_id && !connected.contains(peer_id))
+        .take(PROVIDERS_PER_AUTHOR)
+        .collect::<Vec<_>>();
+
+    let mut any_connected = false;
+    for peer_id in providers {
+        let result = match network.find_peer(peer_id).await {
+            Ok(addrs) => network.connect(peer_id, addrs).await,
+            Err(error) => Err(error),
+        };
+        debug!(author = %author_id, %peer_id, ?result, "Connecting to provider finished");
+        any_connected |= result.is_ok();
+    }
+    Ok(any_connected)
 }
 
 struct AppState {
-    #[allow(unused)]
     config: Configuration,
     state: State,
+
+    account_key_path: PathBuf,
+    account_key: Option<Keypair>,
+}
+
+fn multiaddrs(addrs: &[crate::configuration::Multiaddr]) -> Result<Vec<Multiaddr>, Error> {
+    addrs.iter().cloned().map(Multiaddr::try_from).collect()
+}
+
+fn parse_peer_id(peer_id: &str) -> Result<libp2p::PeerId, Error> {
+    peer_id
+        .parse()
+        .map_err(|_| Error::ParsePeerId(peer_id.to_string()))
 }
 
 impl AppState {
-    fn get_latest_post(&self) -> Result<Option<cid::Cid>, Error> {
-        self.state
-            .latest_post()
-            .map(|bytes| cid::Cid::read_bytes(Cursor::new(bytes)))
-            .transpose()
-            .map_err(Error::from)
+    fn get_latest_post(&self) -> Option<cid::Cid> {
+        self.state.latest_post()
     }
 
     async fn set_latest_post(&mut self, post: cid::Cid) -> Result<(), Error> {
-        let bytes = post.to_bytes();
-        self.state.store_latest_post(bytes).await
+        self.state.store_latest_post(post).await
+    }
+
+    fn get_pending_heads(&self) -> Vec<cid::Cid> {
+        self.state.pending_heads().collect()
+    }
+
+    async fn add_pending_head(&mut self, head: cid::Cid) -> Result<(), Error> {
+        self.state.add_pending_head(head).await
+    }
+
+    fn get_account(&self) -> Option<cid::Cid> {
+        self.state.account()
+    }
+
+    async fn set_account(&mut self, account: cid::Cid) -> Result<(), Error> {
+        self.state.store_account(account).await
+    }
+
+    fn get_followed_account(&self, author: &str) -> Option<cid::Cid> {
+        self.state
+            .follow(author)
+            .and_then(|follow| follow.account())
     }
 }
@@ -0,0 +1,184 @@
+//! Reading and writing of CARv1 files
+//!
+//! A CAR file starts with a varint length prefixed DAG-CBOR header naming the roots, followed by
+//! varint length prefixed sections of CID bytes and block data.
+//! See <https://ipld.io/specs/transport/car/carv1/>.
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use libipld::prelude::Codec;
+use libipld::Ipld;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+
+use crate::error::Error;
+
+const CAR_VERSION: i128 = 1;
+
+/// Sections larger than this are considered corrupt rather than allocated
+const MAX_SECTION_LENGTH: u64 = 32 * 1024 * 1024;
+
+pub struct CarWriter<W> {
+    writer: W,
+}
+
+impl<W: AsyncWrite + Unpin> CarWriter<W> {
+    pub async fn new(mut writer: W, roots: &[cid::Cid]) -> Result<Self, Error> {
+        let header = Ipld::Map(BTreeMap::from([
+            (
+                "roots".to_string(),
+                Ipld::List(roots.iter().cloned().map(Ipld::Link).collect()),
+            ),
+            ("version".to_string(), Ipld::Integer(CAR_VERSION)),
+        ]));
+        let header = libipld::cbor::DagCborCodec.encode(&header)?;
+
+        write_varint(&mut writer, header.len() as u64).await?;
+        writer.write_all(&header).await.map_err(Error::WritingCar)?;
+        Ok(CarWriter { writer })
+    }
+
+    pub async fn write_block(&mut self, cid: &cid::Cid, data: &[u8]) -> Result<(), Error> {
+        let cid = cid.to_bytes();
+        write_varint(&mut self.writer, (cid.len() + data.len()) as u64).await?;
+        self.writer
+            .write_all(&cid)
+            .await
+            .map_err(Error::WritingCar)?;
+        self.writer.write_all(data).await.map_err(Error::WritingCar)
+    }
+
+    pub async fn finish(mut self) -> Result<W, Error> {
+        self.writer.flush().await.map_err(Error::WritingCar)?;
+        Ok(self.writer)
+    }
+}
+
+pub struct CarReader<R> {
+    reader: R,
+    roots: Vec<cid::Cid>,
+}
+
+impl<R: AsyncRead + Unpin> CarReader<R> {
+    pub async fn new(mut reader: R) -> Result<Self, Error> {
+        let length = read_varint(&mut reader)
+            .await?
+            .ok_or(Error::InvalidCar("missing header"))?;
+        let header = read_section(&mut reader, length).await?;
+
+        let header: Ipld = libipld::cbor::DagCborCodec.decode(&header)?;
+        if header.get("version").ok() != Some(&Ipld::Integer(CAR_VERSION)) {
+            return Err(Error::InvalidCar("unsupported version"));
+        }
+
+        let roots = match header.get("roots") {
+            Ok(Ipld::List(roots)) => roots
+                .iter()
+                .map(|root| match root {
+                    Ipld::Link(cid) => Ok(*cid),
+                    _ => Err(Error::InvalidCar("root is not a link")),
+                })
+                .collect::<Result<Vec<_>, Error>>()?,
+            _ => return Err(Error::InvalidCar("missing roots")),
+        };
+
+        Ok(CarReader { reader, roots })
+    }
+
+    pub fn roots(&self) -> &[cid::Cid] {
+        &self.roots
+    }
+
+    /// The next block of the file, `None` at the end of the file
+    pub async fn next_block(&mut self) -> Result<Option<(cid::Cid, Vec<u8>)>, Error> {
+        let Some(length) = read_varint(&mut self.reader).await? else {
+            return Ok(None);
+        };
+
+        let section = read_section(&mut self.reader, length).await?;
+        let mut cursor = Cursor::new(section);
+        let cid = cid::Cid::read_bytes(&mut cursor)?;
+        let offset = cursor.position() as usize;
+        let mut data = cursor.into_inner();
+        data.drain(..offset);
+
+        Ok(Some((cid, data)))
+    }
+}
+
+async fn write_varint<W: AsyncWrite + Unpin>(writer: &mut W, mut value: u64) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(10);
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+
+    writer.write_all(&buf).await.map_err(Error::WritingCar)
+}
+
+/// Read an unsigned varint, `None` if the reader is at its end
+async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<u64>, Error> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        if reader.read(&mut byte).await.map_err(Error::ReadingCar)? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err(Error::InvalidCar("truncated varint"))
+            };
+        }
+
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+
+    Err(Error::InvalidCar("varint too long"))
+}
+
+async fn read_section<R: AsyncRead + Unpin>(reader: &mut R, length: u64) -> Result<Vec<u8>, Error> {
+    if length > MAX_SECTION_LENGTH {
+        return Err(Error::InvalidCar("section too large"));
+    }
+
+    let mut buf = vec![0u8; length as usize];
+    reader
+        .read_exact(&mut buf)
+        .await
+        .map_err(Error::ReadingCar)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use libipld::multihash::MultihashDigest;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let data = b"some block".to_vec();
+        let hash = libipld::multihash::Code::Sha2_256.digest(&data);
+        let cid = cid::Cid::new_v1(0x55, hash);
+
+        let mut writer = CarWriter::new(Vec::new(), &[cid]).await.unwrap();
+        writer.write_block(&cid, &data).await.unwrap();
+        let file = writer.finish().await.unwrap();
+
+        let mut reader = CarReader::new(file.as_slice()).await.unwrap();
+        assert_eq!(reader.roots(), &[cid]);
+        assert_eq!(reader.next_block().await.unwrap(), Some((cid, data)));
+        assert_eq!(reader.next_block().await.unwrap(), None);
+    }
+}
@@ -0,0 +1,47 @@
+/// A CID as it is written to the state and configuration files
+///
+/// The CID is stored as multibase string, the same form users see and paste, so the files can be
+/// read and edited by hand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct CidString(pub(crate) cid::Cid);
+
+impl From<cid::Cid> for CidString {
+    fn from(cid: cid::Cid) -> Self {
+        CidString(cid)
+    }
+}
+
+impl serde::Serialize for CidString {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CidString {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        cid::Cid::try_from(text.as_str())
+            .map(CidString)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct Entry {
+        cid: CidString,
+    }
+
+    #[test]
+    fn test_multibase_string() {
+        let text = "cid = \"bafyreic6dyv4vqyfswfsob34ue3pgxykxlt46ogjv5ty67jcb3imwuou7a\"\n";
+        let entry: Entry = toml::from_str(text).unwrap();
+        assert_eq!(entry.cid.0.codec(), 0x71);
+        assert_eq!(toml::to_string(&entry).unwrap(), text);
+
+        assert!(toml::from_str::<Entry>("cid = \"not a cid\"").is_err());
+    }
+}
@@ -1,12 +1,283 @@
-pub type CommandReceiver = tokio::sync::mpsc::Receiver<Command>;
-pub type CommandSender = tokio::sync::mpsc::Sender<Command>;
+use std::path::PathBuf;
 
-/// A command gets send from the frontend to the backend
+use crate::configuration::ConfigChanges;
+use crate::configuration::NetworkSettings;
+use crate::error::Error;
+use crate::timeline::TimelineCursor;
+use crate::timeline::TimelineEntry;
+
+pub type CommandResult = Result<CommandOutput, Error>;
+
+/// Create the channel the frontends use to send commands to the backend
+pub fn channel(capacity: usize) -> (CommandSender, CommandReceiver) {
+    let (sender, receiver) = tokio::sync::mpsc::channel(capacity);
+    let queue = sender.downgrade();
+    (
+        CommandSender { sender },
+        CommandReceiver { receiver, queue },
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct CommandSender {
+    sender: tokio::sync::mpsc::Sender<CommandRequest>,
+}
+
+impl CommandSender {
+    /// Send a command without waiting for its result
+    pub async fn send(&self, command: Command) -> Result<(), Error> {
+        self.sender
+            .send(CommandRequest {
+                command,
+                reply: None,
+            })
+            .await
+            .map_err(|_| Error::BackendGone)
+    }
+
+    /// Send a command and wait until the backend handled it
+    pub async fn request(&self, command: Command) -> CommandResult {
+        let (reply, result) = tokio::sync::oneshot::channel();
+        self.sender
+            .send(CommandRequest {
+                command,
+                reply: Some(reply),
+            })
+            .await
+            .map_err(|_| Error::BackendGone)?;
+
+        result.await.map_err(|_| Error::BackendGone)?
+    }
+}
+
+pub struct CommandReceiver {
+    receiver: tokio::sync::mpsc::Receiver<CommandRequest>,
+
+    /// Only used to look at the queue, does not keep the channel open
+    queue: tokio::sync::mpsc::WeakSender<CommandRequest>,
+}
+
+impl CommandReceiver {
+    pub async fn recv(&mut self) -> Option<CommandRequest> {
+        self.receiver.recv().await
+    }
+
+    /// How many commands wait for being handled
+    pub fn queue_depth(&self) -> usize {
+        self.queue
+            .upgrade()
+            .map(|sender| sender.max_capacity() - sender.capacity())
+            .unwrap_or_default()
+    }
+}
+
+/// A command together with the way back to whoever sent it
 #[derive(Debug)]
+pub struct CommandRequest {
+    pub command: Command,
+    reply: Option<tokio::sync::oneshot::Sender<CommandResult>>,
+}
+
+impl CommandRequest {
+    /// Hand the result to the sender, if it waits for one
+    pub fn respond(self, result: CommandResult) {
+        if let Some(reply) = self.reply {
+            let _ = reply.send(result);
+        }
+    }
+}
+
+/// What a command produced
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommandOutput {
+    /// The command has nothing to report
+    Done,
+
+    /// A node was added to our timeline
+    Node {
+        node_id: String,
+    },
+
+    /// The account this device posts for, after the command
+    Account {
+        account_id: String,
+        account_record: String,
+    },
+
+    GarbageCollected {
+        removed_blocks: usize,
+        freed_bytes: u64,
+    },
+
+    /// Our own timeline merged with those of the authors we follow, newest first
+    Timeline {
+        entries: Vec<TimelineEntry>,
+
+        /// Where the feed continues after `entries`, if there is more
+        #[serde(default)]
+        next: Option<TimelineCursor>,
+    },
+
+    Peers {
+        peers: Vec<Peer>,
+    },
+
+    /// Who this device is, and the account it posts for if there is one
+    Identity {
+        device_id: String,
+        account_id: Option<String>,
+        account_record: Option<String>,
+    },
+
+    /// The configuration file was read again
+    ConfigReloaded(ConfigChanges),
+
+    /// The `[network]` section of the configuration file
+    NetworkSettings(NetworkSettings),
+
+    CarExported {
+        path: PathBuf,
+        blocks: usize,
+    },
+
+    CarImported {
+        path: PathBuf,
+        roots: Vec<String>,
+        blocks: usize,
+    },
+}
+
+/// A peer we are connected to
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Peer {
+    pub peer_id: String,
+    pub addrs: Vec<String>,
+}
+
+/// A command gets send from the frontend to the backend
+///
+/// Serialized, a command looks like `{"method": "post_text", "params": {"text": "Hello"}}`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
 pub enum Command {
     QuitApp,
 
-    PostText { text: String },
+    PostText {
+        text: String,
+    },
+
+    ConnectTo {
+        uri: String,
+    },
+
+    /// Create a new account with this device as its first device
+    CreateAccount,
+
+    /// Allow another device, identified by its PeerId, to post on behalf of our account
+    AddDevice {
+        peer_id: String,
+    },
+
+    /// Join the account with the given account record CID
+    JoinAccount {
+        account: String,
+    },
+
+    /// Follow the timeline of an account, identified by its account id
+    Follow {
+        author: String,
+    },
+
+    Unfollow {
+        author: String,
+    },
+
+    /// Remove everything from the blockstore that is not pinned
+    CollectGarbage,
+
+    /// Add a bootstrap node to the configuration and connect to it
+    AddBootstrapNode {
+        addr: String,
+    },
+
+    /// Remove a bootstrap node from the configuration
+    RemoveBootstrapNode {
+        addr: String,
+    },
+
+    /// Tell the network about the post in node `node_id` of `author`
+    ///
+    /// Without a community, the announcement goes to the global announcement topic.
+    Announce {
+        author: String,
+        node_id: String,
+        community: Option<String>,
+    },
+
+    /// Get up to `limit` posts of our own and the followed timelines, from the local blockstore
+    ///
+    /// Without a cursor, the feed starts at the newest posts. With the `next` cursor of an earlier
+    /// answer, it continues after the posts of that answer.
+    Timeline {
+        limit: usize,
+        #[serde(default)]
+        after: Option<TimelineCursor>,
+    },
+
+    /// List the peers we are connected to
+    Peers,
+
+    Whoami,
+
+    /// Read the configuration file again and apply what can change while running
+    ReloadConfig,
+
+    /// Get the `[network]` section of the configuration file, for editing it
+    NetworkSettings,
+
+    /// Check and save a new `[network]` section, then apply it like `ReloadConfig`
+    SetNetworkSettings {
+        settings: NetworkSettings,
+    },
+
+    /// Write everything reachable from the node `head` into a CAR file at `path`
+    ///
+    /// Without a head, our own timeline is exported. The file is written by the backend, so a
+    /// relative path is relative to its working directory.
+    ExportCar {
+        head: Option<String>,
+        path: PathBuf,
+    },
+
+    /// Import all blocks of the CAR file at `path` and pin its roots
+    ImportCar {
+        path: PathBuf,
+    },
+}
 
-    ConnectTo { uri: String },
+impl Command {
+    /// The serialized names of all commands
+    pub const METHODS: &'static [&'static str] = &[
+        "quit_app",
+        "post_text",
+        "connect_to",
+        "create_account",
+        "add_device",
+        "join_account",
+        "follow",
+        "unfollow",
+        "collect_garbage",
+        "add_bootstrap_node",
+        "remove_bootstrap_node",
+        "announce",
+        "timeline",
+        "peers",
+        "whoami",
+        "reload_config",
+        "network_settings",
+        "set_network_settings",
+        "export_car",
+        "import_car",
+    ];
 }
@@ -1,36 +1,141 @@
+use std::path::Path;
 use std::path::PathBuf;
 
-use tokio::io::AsyncWriteExt;
-
 use crate::error::Error;
+use crate::file::ReadError;
+
+/// Listen on all interfaces, on a port chosen by the system
+const DEFAULT_LISTENING_ADDR: &str = "/ip4/0.0.0.0/tcp/0";
 
+#[derive(Clone)]
 pub struct Configuration {
     path: PathBuf,
     config: Config,
 }
 
 impl Configuration {
+    /// The configuration of a fresh installation, keeping the blockstore at `storage_path`
+    ///
+    /// Nothing is written until `save` is called.
+    pub fn with_defaults(path: PathBuf, storage_path: PathBuf) -> Self {
+        Configuration {
+            path,
+            config: Config {
+                network: Network {
+                    storage_path,
+                    bootstrap_nodes: Vec::new(),
+                    listening_addrs: vec![Multiaddr(DEFAULT_LISTENING_ADDR.to_string())],
+                    quota_bytes: None,
+                    discovery: Discovery::default(),
+                    overrides: Overrides::default(),
+                },
+                pinning: Pinning::default(),
+                announce: Announce::default(),
+                metrics: Metrics::default(),
+            },
+        }
+    }
+
     pub async fn load_from_path(path: PathBuf) -> Result<Self, Error> {
-        tokio::fs::read_to_string(&path)
+        crate::file::read_with_backup(&path, |text| toml::from_str(text).map_err(ReadError::Parse))
             .await
-            .map_err(Error::ReadingConfig)
-            .and_then(|text| toml::from_str(&text).map_err(Error::ParsingConfig))
+            .map_err(|error| match error {
+                ReadError::Io(error) => Error::ReadingConfig(error),
+                ReadError::Parse(error) | ReadError::Unsupported(error) => {
+                    Error::ParsingConfig(error)
+                }
+            })
             .map(|config| Configuration { path, config })
     }
 
+    /// Load the file again, keeping the overrides of the network settings
+    ///
+    /// A configuration with problems is rejected as a whole.
+    pub async fn reload(&self) -> Result<Configuration, Error> {
+        let config = self.reread().await?;
+
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(Error::InvalidConfiguration(problems));
+        }
+        Ok(config)
+    }
+
+    /// Load the file again, keeping the overrides of the network settings, without checking it
+    ///
+    /// The file may contain changes that are not in effect yet, so it is read again before it is
+    /// changed and saved.
+    pub async fn reread(&self) -> Result<Configuration, Error> {
+        let mut config = Configuration::load_from_path(self.path.clone()).await?;
+        config.config.network.overrides = self.config.network.overrides.clone();
+        Ok(config)
+    }
+
+    /// The keys of the settings that differ in `other`, as far as they are in effect
+    pub(crate) fn changed_keys(&self, other: &Configuration) -> Vec<&'static str> {
+        let (old, new) = (&self.config, &other.config);
+        [
+            (
+                "network.storage_path",
+                old.network.storage_path() != new.network.storage_path(),
+            ),
+            (
+                "network.bootstrap_nodes",
+                old.network.bootstrap_nodes() != new.network.bootstrap_nodes(),
+            ),
+            (
+                "network.listening_addrs",
+                old.network.listening_addrs() != new.network.listening_addrs(),
+            ),
+            (
+                "network.quota_bytes",
+                old.network.quota_bytes() != new.network.quota_bytes(),
+            ),
+            (
+                "network.discovery",
+                old.network.discovery != new.network.discovery,
+            ),
+            ("pinning", old.pinning != new.pinning),
+            (
+                "announce.communities",
+                old.announce.communities != new.announce.communities,
+            ),
+            ("metrics.listen", old.metrics.listen != new.metrics.listen),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(key, _)| key)
+        .collect()
+    }
+
+    /// Take the setting `key`, as named by `changed_keys`, from `other`
+    pub(crate) fn take_key(&mut self, key: &str, other: &Configuration) {
+        let (this, other) = (&mut self.config, &other.config);
+        match key {
+            "network.storage_path" => {
+                this.network.storage_path = other.network.storage_path.clone();
+            }
+            "network.bootstrap_nodes" => {
+                this.network.bootstrap_nodes = other.network.bootstrap_nodes.clone();
+            }
+            "network.listening_addrs" => {
+                this.network.listening_addrs = other.network.listening_addrs.clone();
+            }
+            "network.quota_bytes" => this.network.quota_bytes = other.network.quota_bytes,
+            "network.discovery" => this.network.discovery = other.network.discovery.clone(),
+            "pinning" => this.pinning = other.pinning.clone(),
+            "announce.communities" => {
+                this.announce.communities = other.announce.communities.clone();
+            }
+            "metrics.listen" => this.metrics.listen = other.metrics.listen,
+            _ => {}
+        }
+    }
+
     pub async fn save(&self) -> Result<(), Error> {
         let config = toml::to_string(&self.config).map_err(Error::SerializingConfig)?;
 
-        tokio::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&self.path)
-            .await
-            .map_err(|source| Error::OpenConfigFile {
-                path: self.path.to_path_buf(),
-                source,
-            })?
-            .write_all(config.as_bytes())
+        crate::file::write_atomically(&self.path, config.as_bytes())
             .await
             .map_err(|source| Error::WritingConfig {
                 path: self.path.to_path_buf(),
@@ -38,40 +143,444 @@ impl Configuration {
             })
     }
 
+    /// Use these `[network]` settings instead of those of the file, without writing them to it
+    pub fn override_network(&mut self, overrides: NetworkOverrides) {
+        let into_multiaddrs = |addrs: Vec<String>| addrs.into_iter().map(Multiaddr).collect();
+
+        self.config.network.overrides = Overrides {
+            storage_path: overrides.storage_path,
+            bootstrap_nodes: overrides.bootstrap_nodes.map(into_multiaddrs),
+            listening_addrs: overrides.listening_addrs.map(into_multiaddrs),
+            quota_bytes: overrides.quota_bytes,
+        };
+    }
+
+    /// The `[network]` settings as they are in the file, without overrides
+    pub fn network_settings(&self) -> NetworkSettings {
+        let network = &self.config.network;
+        let strings = |addrs: &[Multiaddr]| addrs.iter().map(|addr| addr.0.clone()).collect();
+
+        let overrides = &network.overrides;
+        let overridden = [
+            ("network.storage_path", overrides.storage_path.is_some()),
+            (
+                "network.bootstrap_nodes",
+                overrides.bootstrap_nodes.is_some(),
+            ),
+            (
+                "network.listening_addrs",
+                overrides.listening_addrs.is_some(),
+            ),
+            ("network.quota_bytes", overrides.quota_bytes.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, overridden)| *overridden)
+        .map(|(key, _)| key.to_string())
+        .collect();
+
+        NetworkSettings {
+            storage_path: network.storage_path.clone(),
+            bootstrap_nodes: strings(&network.bootstrap_nodes),
+            listening_addrs: strings(&network.listening_addrs),
+            quota_bytes: network.quota_bytes,
+            mdns: network.discovery.mdns,
+            upnp: network.discovery.upnp,
+            overridden,
+        }
+    }
+
+    /// Replace the `[network]` settings, overrides still take precedence
+    ///
+    /// Nothing is checked or written, see `validate` and `save`.
+    pub fn set_network_settings(&mut self, settings: NetworkSettings) {
+        let network = &mut self.config.network;
+        network.storage_path = settings.storage_path;
+        network.bootstrap_nodes = settings
+            .bootstrap_nodes
+            .into_iter()
+            .map(Multiaddr)
+            .collect();
+        network.listening_addrs = settings
+            .listening_addrs
+            .into_iter()
+            .map(Multiaddr)
+            .collect();
+        network.quota_bytes = settings.quota_bytes;
+        network.discovery = Discovery {
+            mdns: settings.mdns,
+            upnp: settings.upnp,
+        };
+    }
+
+    /// Check the configuration for mistakes that would otherwise only show once distrox runs, or
+    /// not at all
+    ///
+    /// All problems are collected, so they can be fixed in one go.
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let network = &self.config.network;
+
+        if let Err(message) = check_storage_path(network.storage_path()) {
+            problems.push(ConfigProblem::new("network.storage_path", message));
+        }
+
+        check_multiaddrs(
+            "network.bootstrap_nodes",
+            network.bootstrap_nodes(),
+            &mut problems,
+        );
+        check_multiaddrs(
+            "network.listening_addrs",
+            network.listening_addrs(),
+            &mut problems,
+        );
+
+        if network.quota_bytes() == Some(0) {
+            problems.push(ConfigProblem::new(
+                "network.quota_bytes",
+                "must be greater than 0, leave it out for no quota".to_string(),
+            ));
+        }
+
+        for (n, community) in self.config.announce.communities.iter().enumerate() {
+            let key = format!("announce.communities[{n}]");
+            if community.trim().is_empty() {
+                problems.push(ConfigProblem::new(&key, "must not be empty".to_string()));
+            } else if let Some(first) = self.config.announce.communities[..n]
+                .iter()
+                .position(|c| c == community)
+            {
+                problems.push(ConfigProblem::new(
+                    &key,
+                    format!("same community as announce.communities[{first}]"),
+                ));
+            }
+        }
+
+        problems
+    }
+
     pub fn network(&self) -> &Network {
         &self.config.network
     }
+
+    pub fn network_mut(&mut self) -> &mut Network {
+        &mut self.config.network
+    }
+
+    pub fn pinning(&self) -> &Pinning {
+        &self.config.pinning
+    }
+
+    pub fn announce(&self) -> &Announce {
+        &self.config.announce
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.config.metrics
+    }
+}
+
+/// The `[network]` section of the configuration, in the form frontends edit it in
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NetworkSettings {
+    pub storage_path: PathBuf,
+    pub bootstrap_nodes: Vec<String>,
+    pub listening_addrs: Vec<String>,
+    pub quota_bytes: Option<u64>,
+    pub mdns: bool,
+    pub upnp: bool,
+
+    /// The keys of the settings that a command line option or `DISTROX_*` variable overrides
+    ///
+    /// Their values here are still those of the file, changing them has no effect as long as
+    /// they are overridden.
+    #[serde(default)]
+    pub overridden: Vec<String>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// How a reload changed the configuration, by the keys of the changed settings
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ConfigChanges {
+    /// Settings that are in effect already
+    pub applied: Vec<String>,
+
+    /// Settings that only take effect once distrox is restarted
+    pub restart_required: Vec<String>,
+}
+
+/// A mistake in the configuration
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct ConfigProblem {
+    /// Where the mistake is, like `network.listening_addrs[1]`
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn new(key: &str, message: String) -> Self {
+        ConfigProblem {
+            key: key.to_string(),
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+/// The blockstore is created if it is missing, so the closest existing directory has to be
+/// writable
+fn check_storage_path(path: &Path) -> Result<(), String> {
+    if path.exists() && !path.is_dir() {
+        return Err(format!("{} is not a directory", path.display()));
+    }
+
+    let dir = path
+        .ancestors()
+        .map(|dir| match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        })
+        .find(|dir| dir.exists())
+        .unwrap_or(Path::new("."));
+
+    let probe = dir.join(format!(".distrox-write-check-{}", std::process::id()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|error| format!("{} is not writable: {error}", dir.display()))
+}
+
+fn check_multiaddrs(key: &str, addrs: &[Multiaddr], problems: &mut Vec<ConfigProblem>) {
+    for (n, addr) in addrs.iter().enumerate() {
+        let item_key = format!("{key}[{n}]");
+        if let Err(error) = addr.0.parse::<libp2p::Multiaddr>() {
+            problems.push(ConfigProblem::new(
+                &item_key,
+                format!("\"{}\" is not a multiaddr: {error}", addr.0),
+            ));
+        }
+
+        if let Some(first) = addrs[..n].iter().position(|a| a.0 == addr.0) {
+            problems.push(ConfigProblem::new(
+                &item_key,
+                format!("same address as {key}[{first}]"),
+            ));
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 struct Config {
     network: Network,
+
+    #[serde(default)]
+    pinning: Pinning,
+
+    #[serde(default)]
+    announce: Announce,
+
+    #[serde(default)]
+    metrics: Metrics,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Network {
     storage_path: PathBuf,
     bootstrap_nodes: Vec<Multiaddr>,
     listening_addrs: Vec<Multiaddr>,
+
+    /// Maximum size of the blockstore, unpinned data is evicted if it grows beyond
+    #[serde(default)]
+    quota_bytes: Option<u64>,
+
+    #[serde(default)]
+    discovery: Discovery,
+
+    #[serde(skip)]
+    overrides: Overrides,
+}
+
+/// Finding peers without bootstrap nodes, and being found
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Discovery {
+    /// Find peers in the local network via multicast DNS
+    #[serde(default = "enabled")]
+    mdns: bool,
+
+    /// Ask the router to forward a port to us via UPnP, so peers from outside can connect
+    #[serde(default = "enabled")]
+    upnp: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            mdns: true,
+            upnp: true,
+        }
+    }
+}
+
+impl Discovery {
+    pub(crate) fn mdns(&self) -> bool {
+        self.mdns
+    }
+
+    pub(crate) fn upnp(&self) -> bool {
+        self.upnp
+    }
+}
+
+/// Settings of `[network]` that take precedence over the configuration file, like those given
+/// as environment variables or command line flags
+#[derive(Clone, Debug, Default)]
+pub struct NetworkOverrides {
+    pub storage_path: Option<PathBuf>,
+    pub bootstrap_nodes: Option<Vec<String>>,
+    pub listening_addrs: Option<Vec<String>>,
+    pub quota_bytes: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Overrides {
+    storage_path: Option<PathBuf>,
+    bootstrap_nodes: Option<Vec<Multiaddr>>,
+    listening_addrs: Option<Vec<Multiaddr>>,
+    quota_bytes: Option<u64>,
 }
 
 impl Network {
     pub(crate) fn storage_path(&self) -> &PathBuf {
-        &self.storage_path
+        self.overrides
+            .storage_path
+            .as_ref()
+            .unwrap_or(&self.storage_path)
     }
 
     pub(crate) fn bootstrap_nodes(&self) -> &[Multiaddr] {
-        self.bootstrap_nodes.as_ref()
+        self.overrides
+            .bootstrap_nodes
+            .as_ref()
+            .unwrap_or(&self.bootstrap_nodes)
     }
 
     pub(crate) fn listening_addrs(&self) -> &[Multiaddr] {
-        self.listening_addrs.as_ref()
+        self.overrides
+            .listening_addrs
+            .as_ref()
+            .unwrap_or(&self.listening_addrs)
+    }
+
+    pub(crate) fn quota_bytes(&self) -> Option<u64> {
+        self.overrides.quota_bytes.or(self.quota_bytes)
+    }
+
+    pub(crate) fn discovery(&self) -> &Discovery {
+        &self.discovery
+    }
+
+    /// Add a bootstrap node, returns false if it was already configured
+    ///
+    /// Overridden bootstrap nodes get it as well, so it is used right away.
+    pub(crate) fn add_bootstrap_node(&mut self, addr: Multiaddr) -> bool {
+        if let Some(overridden) = self.overrides.bootstrap_nodes.as_mut() {
+            if !overridden.iter().any(|n| n.0 == addr.0) {
+                overridden.push(addr.clone());
+            }
+        }
+
+        if self.bootstrap_nodes.iter().any(|n| n.0 == addr.0) {
+            return false;
+        }
+
+        self.bootstrap_nodes.push(addr);
+        true
+    }
+
+    /// Remove a bootstrap node, returns false if it was not configured
+    pub(crate) fn remove_bootstrap_node(&mut self, addr: &Multiaddr) -> bool {
+        if let Some(overridden) = self.overrides.bootstrap_nodes.as_mut() {
+            overridden.retain(|n| n.0 != addr.0);
+        }
+
+        let len = self.bootstrap_nodes.len();
+        self.bootstrap_nodes.retain(|n| n.0 != addr.0);
+        len != self.bootstrap_nodes.len()
     }
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+/// Which content of followed authors is kept in the local blockstore
+///
+/// Our own content is always kept, content that is not covered by this is removed by garbage
+/// collection.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Pinning {
+    /// Keep posts of followed authors only up to this age
+    followed_max_age_days: Option<u64>,
+
+    /// Keep at most this many bytes per followed author
+    followed_max_bytes: Option<u64>,
+}
+
+impl Pinning {
+    pub(crate) fn followed_max_age_days(&self) -> Option<u64> {
+        self.followed_max_age_days
+    }
+
+    pub(crate) fn followed_max_bytes(&self) -> Option<u64> {
+        self.followed_max_bytes
+    }
+}
+
+/// Where announcements of posts are exchanged
+///
+/// The global announcement topic is always used, communities get a topic of their own.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Announce {
+    #[serde(default)]
+    communities: Vec<String>,
+}
+
+impl Announce {
+    pub(crate) fn communities(&self) -> &[String] {
+        &self.communities
+    }
+}
+
+/// Exposing metrics for monitoring, in the Prometheus text format
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Metrics {
+    /// Address to serve `/metrics` on, nothing is served if unset
+    #[serde(default)]
+    listen: Option<std::net::SocketAddr>,
+}
+
+impl Metrics {
+    pub(crate) fn listen(&self) -> Option<std::net::SocketAddr> {
+        self.listen
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub(crate) struct Multiaddr(String);
 
+impl From<libp2p::Multiaddr> for Multiaddr {
+    fn from(m: libp2p::Multiaddr) -> Self {
+        Multiaddr(m.to_string())
+    }
+}
+
 impl TryFrom<Multiaddr> for libp2p::Multiaddr {
     type Error = Error;
 
@@ -82,3 +591,157 @@ impl TryFrom<Multiaddr> for libp2p::Multiaddr {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_parse() {
+        let config = Configuration::with_defaults("config.toml".into(), "blockstore".into());
+        let text = toml::to_string(&config.config).unwrap();
+        let parsed: Config = toml::from_str(&text).unwrap();
+
+        assert_eq!(parsed.network.storage_path, PathBuf::from("blockstore"));
+        assert!(parsed.network.bootstrap_nodes.is_empty());
+        let listening = parsed.network.listening_addrs[0].clone();
+        assert!(libp2p::Multiaddr::try_from(listening).is_ok());
+    }
+
+    #[test]
+    fn test_validate_collects_problems() {
+        let text = r#"
+            [network]
+            storage_path = "/dev/null/blockstore"
+            bootstrap_nodes = [
+                "/ip4/192.0.2.1/tcp/4001/p2p/12D3KooWLfUXLKHAPnT8Ls5JkcqhfZUwrKhXYkPNF7yajGmzbdJp",
+                "/ip4/192.0.2.2/tcpx/4001",
+                "/ip4/192.0.2.1/tcp/4001/p2p/12D3KooWLfUXLKHAPnT8Ls5JkcqhfZUwrKhXYkPNF7yajGmzbdJp",
+            ]
+            listening_addrs = ["/ip4/0.0.0.0/tcp/4001", "/ip4/0.0.0.0/tcp/4001"]
+
+            [announce]
+            communities = ["rust", ""]
+        "#;
+        let config = Configuration {
+            path: "config.toml".into(),
+            config: toml::from_str(text).unwrap(),
+        };
+
+        let keys = config
+            .validate()
+            .into_iter()
+            .map(|problem| problem.key)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "network.storage_path",
+                "network.bootstrap_nodes[1]",
+                "network.bootstrap_nodes[2]",
+                "network.listening_addrs[1]",
+                "announce.communities[1]",
+            ]
+        );
+
+        let defaults = Configuration::with_defaults("config.toml".into(), std::env::temp_dir());
+        assert!(defaults.validate().is_empty());
+    }
+
+    #[test]
+    fn test_network_settings() {
+        let text = r#"
+            [network]
+            storage_path = "blockstore"
+            bootstrap_nodes = []
+            listening_addrs = ["/ip4/0.0.0.0/tcp/4001"]
+        "#;
+        let mut config = Configuration {
+            path: "config.toml".into(),
+            config: toml::from_str(text).unwrap(),
+        };
+
+        // Configurations from before discovery could be turned off
+        let mut settings = config.network_settings();
+        assert!(settings.mdns && settings.upnp);
+
+        settings.bootstrap_nodes = vec!["/ip4/192.0.2.1/tcp/4001".to_string()];
+        settings.quota_bytes = Some(1 << 30);
+        settings.upnp = false;
+        config.set_network_settings(settings.clone());
+        assert_eq!(config.network_settings(), settings);
+        assert_eq!(
+            config.changed_keys(&Configuration {
+                path: "config.toml".into(),
+                config: toml::from_str(text).unwrap(),
+            }),
+            [
+                "network.bootstrap_nodes",
+                "network.quota_bytes",
+                "network.discovery"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_changed_keys() {
+        let old = Configuration::with_defaults("config.toml".into(), "blockstore".into());
+        let mut new = Configuration::with_defaults("config.toml".into(), "blockstore".into());
+        assert!(old.changed_keys(&new).is_empty());
+
+        new.network_mut()
+            .add_bootstrap_node(Multiaddr("/ip4/192.0.2.1/tcp/4001".to_string()));
+        new.config.metrics.listen = Some(([127, 0, 0, 1], 9100).into());
+        assert_eq!(
+            old.changed_keys(&new),
+            ["network.bootstrap_nodes", "metrics.listen"]
+        );
+
+        // Overridden settings stay the same, whatever the file says
+        new.override_network(NetworkOverrides {
+            bootstrap_nodes: Some(Vec::new()),
+            ..Default::default()
+        });
+        assert_eq!(old.changed_keys(&new), ["metrics.listen"]);
+    }
+
+    #[test]
+    fn test_take_key() {
+        let mut old = Configuration::with_defaults("config.toml".into(), "blockstore".into());
+        let mut new = Configuration::with_defaults("config.toml".into(), "blockstore".into());
+        new.network_mut()
+            .add_bootstrap_node(Multiaddr("/ip4/192.0.2.1/tcp/4001".to_string()));
+        new.config.metrics.listen = Some(([127, 0, 0, 1], 9100).into());
+
+        old.take_key("network.bootstrap_nodes", &new);
+        assert_eq!(old.changed_keys(&new), ["metrics.listen"]);
+        old.take_key("metrics.listen", &new);
+        assert!(old.changed_keys(&new).is_empty());
+    }
+
+    #[test]
+    fn test_overrides_are_not_saved() {
+        let mut config = Configuration::with_defaults("config.toml".into(), "blockstore".into());
+        config.override_network(NetworkOverrides {
+            storage_path: Some("other".into()),
+            listening_addrs: Some(vec!["/ip4/127.0.0.1/tcp/4001".to_string()]),
+            ..Default::default()
+        });
+
+        let network = config.network();
+        assert_eq!(network.storage_path(), &PathBuf::from("other"));
+        assert_eq!(network.listening_addrs()[0].0, "/ip4/127.0.0.1/tcp/4001");
+        assert!(network.bootstrap_nodes().is_empty());
+
+        let settings = config.network_settings();
+        assert_eq!(settings.storage_path, PathBuf::from("blockstore"));
+        assert_eq!(
+            settings.overridden,
+            ["network.storage_path", "network.listening_addrs"]
+        );
+
+        let saved: Config = toml::from_str(&toml::to_string(&config.config).unwrap()).unwrap();
+        assert_eq!(saved.network.storage_path, PathBuf::from("blockstore"));
+        assert_eq!(saved.network.listening_addrs[0].0, DEFAULT_LISTENING_ADDR);
+    }
+}
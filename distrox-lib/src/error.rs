@@ -12,22 +12,33 @@ pub enum Error {
         source: libp2p::multiaddr::Error,
     },
 
+    #[error(transparent)]
+    Xdg(#[from] xdg::BaseDirectoriesError),
+
+    #[error("Invalid profile name \"{0}\", use letters, digits, '-', '_' and '.'")]
+    InvalidProfileName(String),
+
+    #[error("Failed to list profiles")]
+    ListingProfiles(#[source] std::io::Error),
+
+    #[error("Failed to create directory")]
+    CreatingDirectory(#[source] std::io::Error),
+
     #[error("Failed to read configuration")]
     ReadingConfig(#[source] std::io::Error),
 
     #[error("Failed to parse configuration")]
     ParsingConfig(#[source] toml::de::Error),
 
+    #[error(
+        "Invalid configuration: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    InvalidConfiguration(Vec<crate::configuration::ConfigProblem>),
+
     #[error("Failed to serialize config")]
     SerializingConfig(#[source] toml::ser::Error),
 
-    #[error("Opening config file {}", .path.display())]
-    OpenConfigFile {
-        path: PathBuf,
-        #[source]
-        source: std::io::Error,
-    },
-
     #[error("Writing to config file {}", .path.display())]
     WritingConfig {
         path: PathBuf,
@@ -41,16 +52,12 @@ pub enum Error {
     #[error("Failed to parse state")]
     ParsingState(#[source] toml::de::Error),
 
+    #[error("State was written by a newer version of distrox (state version {0})")]
+    UnsupportedStateVersion(u32),
+
     #[error("Failed to serialize state")]
     SerializingState(#[source] toml::ser::Error),
 
-    #[error("Opening state file {}", .path.display())]
-    OpenStateFile {
-        path: PathBuf,
-        #[source]
-        source: std::io::Error,
-    },
-
     #[error("Writing to state file {}", .path.display())]
     WritingState {
         path: PathBuf,
@@ -66,4 +73,113 @@ pub enum Error {
 
     #[error("Unknown cid")]
     UnknownCid,
+
+    #[error("Timed out fetching {}", .0)]
+    FetchTimeout(cid::Cid),
+
+    #[error("Failed to read blob")]
+    ReadingBlob(#[source] rust_ipfs::unixfs::TraversalFailed),
+
+    #[error("Failed to read keypair from {}", .path.display())]
+    ReadingKeypair {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to write keypair to {}", .path.display())]
+    WritingKeypair {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to decode key")]
+    DecodingKeypair(#[source] libp2p::identity::DecodingError),
+
+    #[error("Failed to sign")]
+    Signing(#[source] libp2p::identity::SigningError),
+
+    #[error("Cannot parse peer id: {}", .0)]
+    ParsePeerId(String),
+
+    #[error("An account is already configured")]
+    AccountExists,
+
+    #[error("No account configured")]
+    NoAccount,
+
+    #[error("The account key is not available on this device")]
+    NoAccountKey,
+
+    #[error("Key does not belong to the account")]
+    NotAccountKey,
+
+    #[error("Account contains an invalid device delegation")]
+    InvalidDelegation,
+
+    #[error("This device is not delegated by the account")]
+    DeviceNotDelegated,
+
+    #[error("Account record is not known yet")]
+    UnknownAccount,
+
+    #[error("Cannot follow own account")]
+    FollowingOwnAccount,
+
+    #[error("Failed to read CAR file")]
+    ReadingCar(#[source] std::io::Error),
+
+    #[error("Failed to write CAR file")]
+    WritingCar(#[source] std::io::Error),
+
+    #[error("Invalid CAR file: {}", .0)]
+    InvalidCar(&'static str),
+
+    #[error("Block data does not match its CID {}", .cid)]
+    CarCidMismatch { cid: cid::Cid },
+
+    #[error("CAR file is incomplete, block {} is missing", .missing)]
+    IncompleteCar { missing: cid::Cid },
+
+    #[error("Opening CAR file {}", .path.display())]
+    OpenCarFile {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Nothing to export, there is no timeline yet")]
+    NoTimeline,
+
+    #[error("Not possible while offline")]
+    Offline,
+
+    #[error("The backend is not running anymore")]
+    BackendGone,
+
+    #[error("Failed to listen for metrics requests")]
+    MetricsEndpoint(#[source] std::io::Error),
+
+    #[error("Node {node_id} has no post")]
+    NodeWithoutPost { node_id: cid::Cid },
+
+    #[error("Cannot talk to the daemon at {}", .path.display())]
+    ControlConnection {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to encode request to the daemon")]
+    EncodingRpcRequest(#[source] serde_json::Error),
+
+    #[error("Failed to decode response of the daemon")]
+    DecodingRpcResponse(#[source] serde_json::Error),
+
+    #[error("Invalid response of the daemon: {}", .0)]
+    InvalidRpcResponse(&'static str),
+
+    #[error("{message}")]
+    Rpc { code: i64, message: String },
 }
@@ -1,13 +1,46 @@
 use libp2p::Multiaddr;
-use libp2p_identity::PeerId;
+use libp2p::PeerId;
+
+use crate::configuration::ConfigChanges;
+use crate::metrics::NetworkMetrics;
+
+pub type EventReceiver = tokio::sync::broadcast::Receiver<Event>;
+pub type EventSender = tokio::sync::broadcast::Sender<Event>;
 
 /// An event gets send from the backend to the frontend
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Event {
-    ConnectionEstablished { address: Multiaddr },
-    ConnectionClosed { address: Multiaddr },
+    ConnectionEstablished {
+        address: Multiaddr,
+    },
+    ConnectionClosed {
+        address: Multiaddr,
+    },
 
     PubSubSubscribe(PeerId),
     PubSubUnsubscribe(PeerId),
     // PubSubMessage
+    /// Someone announced a post, which might lead to an author we do not know yet
+    ///
+    /// Only sent once the post was found in the timeline of its author.
+    Discovered(Box<Discovery>),
+
+    /// Sent periodically, for showing the status of the node
+    Metrics(Box<NetworkMetrics>),
+
+    /// The head of our own or a followed timeline moved, and the new posts are available locally
+    TimelineChanged {
+        head: cid::Cid,
+    },
+
+    /// The configuration was reloaded, some changes might only apply after a restart
+    ConfigReloaded(ConfigChanges),
+}
+
+#[derive(Clone, Debug)]
+pub struct Discovery {
+    pub author: PeerId,
+    pub announced_by: PeerId,
+    pub node_id: cid::Cid,
+    pub post_id: cid::Cid,
 }
@@ -0,0 +1,172 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+/// Why a file could not be loaded
+#[derive(Debug)]
+pub(crate) enum ReadError<E> {
+    Io(std::io::Error),
+    Parse(E),
+
+    /// The file is intact, but written by a newer version
+    ///
+    /// Falling back to the backup would silently drop whatever changed since, so this is never
+    /// recovered from.
+    Unsupported(E),
+}
+
+fn sibling(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{prefix}{name}{suffix}"))
+}
+
+/// Where the previous version of `path` is kept
+pub(crate) fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, "", ".bak")
+}
+
+/// Replace the file at `path` with `contents`, without ever leaving a partially written file
+///
+/// The contents go to a temporary file first, which replaces `path` once it is on disk. The
+/// previous version of `path` is kept as backup.
+pub(crate) async fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = write_temp(path, contents, 0o666).await?;
+
+    if tokio::fs::try_exists(path).await? {
+        let backup = backup_path(path);
+        tokio::fs::copy(path, &backup).await?;
+        tokio::fs::File::open(&backup).await?.sync_all().await?;
+    }
+
+    replace(&temp_path, path).await
+}
+
+/// Like `write_atomically`, but only the owner may read the file, and no backup is kept
+pub(crate) async fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = write_temp(path, contents, 0o600).await?;
+    replace(&temp_path, path).await
+}
+
+/// Write `contents` to a new temporary file next to `path`, created with `mode`
+async fn write_temp(path: &Path, contents: &[u8], mode: u32) -> std::io::Result<PathBuf> {
+    let temp_path = sibling(path, ".", ".tmp");
+
+    // A leftover of an interrupted write would keep its mode
+    match tokio::fs::remove_file(&temp_path).await {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+
+    let mut temp = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&temp_path)
+        .await?;
+    temp.write_all(contents).await?;
+    temp.sync_all().await?;
+    Ok(temp_path)
+}
+
+async fn replace(temp_path: &Path, path: &Path) -> std::io::Result<()> {
+    tokio::fs::rename(temp_path, path).await?;
+
+    // The rename itself is only durable once the directory is synced
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::File::open(parent).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+/// Read and parse the file at `path`, using its backup if the file itself is corrupt
+pub(crate) async fn read_with_backup<T, E>(
+    path: &Path,
+    parse: impl Fn(&str) -> Result<T, ReadError<E>>,
+) -> Result<T, ReadError<E>>
+where
+    E: std::fmt::Debug,
+{
+    let error = match tokio::fs::read_to_string(path).await {
+        Ok(text) => match parse(&text) {
+            Ok(value) => return Ok(value),
+            Err(error @ ReadError::Unsupported(_)) => return Err(error),
+            Err(error) => error,
+        },
+        Err(error) if error.kind() == std::io::ErrorKind::InvalidData => ReadError::Io(error),
+        Err(error) => return Err(ReadError::Io(error)),
+    };
+
+    let backup = backup_path(path);
+    let Ok(text) = tokio::fs::read_to_string(&backup).await else {
+        return Err(error);
+    };
+
+    match parse(&text) {
+        Ok(value) => {
+            warn!(path = %path.display(), ?error, "File is corrupt, using its backup");
+            Ok(value)
+        }
+        Err(_) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<u32, ReadError<std::num::ParseIntError>> {
+        text.parse().map_err(ReadError::Parse)
+    }
+
+    #[tokio::test]
+    async fn test_write_and_recover() {
+        let dir = std::env::temp_dir().join(format!("distrox-file-test-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("value");
+
+        write_atomically(&path, b"1").await.unwrap();
+        write_atomically(&path, b"2").await.unwrap();
+        assert_eq!(read_with_backup(&path, parse).await.unwrap(), 2);
+        assert_eq!(tokio::fs::read(backup_path(&path)).await.unwrap(), b"1");
+
+        // A torn write of the main file
+        tokio::fs::write(&path, b"2x").await.unwrap();
+        assert_eq!(read_with_backup(&path, parse).await.unwrap(), 1);
+
+        tokio::fs::write(backup_path(&path), b"").await.unwrap();
+        assert!(matches!(
+            read_with_backup(&path, parse).await,
+            Err(ReadError::Parse(_))
+        ));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("distrox-private-test-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("key");
+
+        write_private(&path, b"1").await.unwrap();
+        write_private(&path, b"2").await.unwrap();
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), b"2");
+        assert!(!tokio::fs::try_exists(backup_path(&path)).await.unwrap());
+
+        let mode = tokio::fs::metadata(&path)
+            .await
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+use futures::stream::BoxStream;
+use futures::stream::SelectAll;
+use futures::StreamExt;
+use libp2p::PeerId;
+
+use crate::error::Error;
+use crate::network::Network;
+
+/// The subscriptions to the timelines of the authors we follow
+///
+/// Authors publish their heads on the same topic their devices use to synchronize, so following
+/// an author means listening to that topic.
+pub(crate) struct FollowSync {
+    messages: SelectAll<BoxStream<'static, (PeerId, libp2p::gossipsub::Message)>>,
+}
+
+impl FollowSync {
+    pub(crate) fn new() -> Self {
+        FollowSync {
+            messages: SelectAll::new(),
+        }
+    }
+
+    pub(crate) async fn subscribe(
+        &mut self,
+        network: &Network,
+        author: PeerId,
+    ) -> Result<(), Error> {
+        let topic = crate::account::sync_topic(&author);
+        let messages = network.subscribe(topic).await?;
+        self.messages
+            .push(messages.map(move |message| (author, message)).boxed());
+        Ok(())
+    }
+
+    /// Unsubscribing ends the stream of messages, which is then dropped from the set
+    pub(crate) async fn unsubscribe(
+        &mut self,
+        network: &Network,
+        author: PeerId,
+    ) -> Result<(), Error> {
+        network
+            .unsubscribe(&crate::account::sync_topic(&author))
+            .await
+    }
+
+    pub(crate) async fn next(&mut self) -> Option<(PeerId, libp2p::gossipsub::Message)> {
+        self.messages.next().await
+    }
+}
@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use libp2p::identity::Keypair;
+
+use crate::error::Error;
+
+/// Load a keypair from `path`
+pub async fn load_keypair(path: &Path) -> Result<Keypair, Error> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|source| Error::ReadingKeypair {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+    Keypair::from_protobuf_encoding(&bytes).map_err(Error::DecodingKeypair)
+}
+
+/// Write a keypair to `path`, creating the parent directories if necessary
+///
+/// Only the owner may read the written file.
+pub async fn store_keypair(path: &Path, keypair: &Keypair) -> Result<(), Error> {
+    let bytes = keypair
+        .to_protobuf_encoding()
+        .map_err(Error::DecodingKeypair)?;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|source| Error::WritingKeypair {
+                path: path.to_path_buf(),
+                source,
+            })?;
+    }
+
+    crate::file::write_private(path, &bytes)
+        .await
+        .map_err(|source| Error::WritingKeypair {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// Load the keypair from `path`, or generate and store a new one if there is none yet
+pub async fn load_or_generate_keypair(path: &Path) -> Result<Keypair, Error> {
+    if tokio::fs::try_exists(path).await.unwrap_or(false) {
+        load_keypair(path).await
+    } else {
+        let keypair = Keypair::generate_ed25519();
+        store_keypair(path, &keypair).await?;
+        Ok(keypair)
+    }
+}
@@ -1,7 +1,19 @@
+pub mod account;
+mod announce;
 pub mod application;
+pub mod car;
+mod cid_string;
 pub mod command;
 pub mod configuration;
 pub mod error;
 pub mod event;
+mod file;
+mod follow;
+pub mod identity;
+pub mod metrics;
 pub mod network;
+pub mod pinning;
+pub mod profile;
+pub mod rpc;
 pub mod state;
+pub mod timeline;
@@ -0,0 +1,444 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+use libp2p::bandwidth::BandwidthSinks;
+use libp2p::PeerId;
+use tracing::debug;
+
+/// How long a client of the metrics endpoint may take to send its request
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// A snapshot of how busy the node is, counted since it was started
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NetworkMetrics {
+    /// Open connections per connected peer
+    pub connections: BTreeMap<PeerId, u32>,
+
+    /// Bytes received and sent over all connections
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+
+    /// Payload bytes received from and sent to each connected peer, over its open connections
+    pub traffic: BTreeMap<PeerId, Traffic>,
+
+    /// Blocks of nodes, posts and accounts that had to be fetched from other peers
+    pub blocks_fetched: u64,
+
+    /// Blocks other peers fetched from us via bitswap
+    pub blocks_served: u64,
+
+    pub gossip_messages_received: u64,
+    pub gossip_messages_published: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Traffic {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl NetworkMetrics {
+    pub fn connection_count(&self) -> u32 {
+        self.connections.values().sum()
+    }
+}
+
+/// Collects the counters of `NetworkMetrics`, shared with the swarm task
+#[derive(Default)]
+pub(crate) struct MetricsRecorder {
+    pub(crate) bandwidth: OnceLock<Arc<BandwidthSinks>>,
+    pub(crate) bitswap_server: OnceLock<beetle_bitswap_next::Server<rust_ipfs::repo::Repo>>,
+    connections: Mutex<HashMap<PeerId, u32>>,
+    traffic: Mutex<HashMap<PeerId, Arc<PeerTraffic>>>,
+    blocks_fetched: AtomicU64,
+    gossip_messages_received: AtomicU64,
+    gossip_messages_published: AtomicU64,
+}
+
+impl MetricsRecorder {
+    pub(crate) fn set_connections(&self, peer_id: PeerId, count: u32) {
+        let mut connections = self.connections.lock().unwrap();
+        if count == 0 {
+            connections.remove(&peer_id);
+            self.traffic.lock().unwrap().remove(&peer_id);
+        } else {
+            connections.insert(peer_id, count);
+        }
+    }
+
+    /// The counters for the traffic of a new connection to `peer_id`
+    pub(crate) fn peer_traffic(&self, peer_id: PeerId) -> Arc<PeerTraffic> {
+        self.traffic
+            .lock()
+            .unwrap()
+            .entry(peer_id)
+            .or_default()
+            .clone()
+    }
+
+    pub(crate) fn block_fetched(&self) {
+        self.blocks_fetched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn gossip_message_received(&self) {
+        self.gossip_messages_received
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn gossip_message_published(&self) {
+        self.gossip_messages_published
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) async fn snapshot(&self) -> NetworkMetrics {
+        let blocks_served = match self.bitswap_server.get() {
+            Some(server) => server
+                .stat()
+                .await
+                .map(|stat| stat.blocks_sent)
+                .unwrap_or_default(),
+            None => 0,
+        };
+
+        NetworkMetrics {
+            connections: self
+                .connections
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(peer_id, count)| (*peer_id, *count))
+                .collect(),
+            bytes_in: self
+                .bandwidth
+                .get()
+                .map(|sinks| sinks.total_inbound())
+                .unwrap_or_default(),
+            bytes_out: self
+                .bandwidth
+                .get()
+                .map(|sinks| sinks.total_outbound())
+                .unwrap_or_default(),
+            traffic: self
+                .traffic
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(peer_id, traffic)| (*peer_id, traffic.snapshot()))
+                .collect(),
+            blocks_fetched: self.blocks_fetched.load(Ordering::Relaxed),
+            blocks_served,
+            gossip_messages_received: self.gossip_messages_received.load(Ordering::Relaxed),
+            gossip_messages_published: self.gossip_messages_published.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Traffic with one peer, shared by the substreams of its connections
+#[derive(Default)]
+pub(crate) struct PeerTraffic {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl PeerTraffic {
+    pub(crate) fn received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Traffic {
+        Traffic {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Everything exposed on the metrics endpoint
+#[derive(Debug, Default, Clone)]
+pub struct MetricsReport {
+    pub network: NetworkMetrics,
+    pub blockstore_bytes: u64,
+    pub posts_published: u64,
+    pub nodes_fetched: u64,
+
+    /// Seconds since the timeline of each followed author was last synchronized
+    pub sync_lag: BTreeMap<String, u64>,
+
+    pub command_queue_depth: usize,
+}
+
+/// Render the report in the Prometheus text exposition format
+pub fn render_prometheus(report: &MetricsReport) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, u64)>| {
+        let _ = writeln!(out, "# HELP distrox_{name} {help}");
+        let _ = writeln!(out, "# TYPE distrox_{name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "distrox_{name}{labels} {value}");
+        }
+    };
+
+    let network = &report.network;
+    metric(
+        "connected_peers",
+        "gauge",
+        "Number of connected peers",
+        vec![(String::new(), network.connections.len() as u64)],
+    );
+    metric(
+        "connections",
+        "gauge",
+        "Number of open connections",
+        vec![(String::new(), u64::from(network.connection_count()))],
+    );
+    metric(
+        "network_received_bytes_total",
+        "counter",
+        "Bytes received from peers",
+        vec![(String::new(), network.bytes_in)],
+    );
+    metric(
+        "network_sent_bytes_total",
+        "counter",
+        "Bytes sent to peers",
+        vec![(String::new(), network.bytes_out)],
+    );
+    metric(
+        "peer_received_bytes_total",
+        "counter",
+        "Payload bytes received from a connected peer",
+        network
+            .traffic
+            .iter()
+            .map(|(peer_id, traffic)| (format!("{{peer=\"{peer_id}\"}}"), traffic.bytes_in))
+            .collect(),
+    );
+    metric(
+        "peer_sent_bytes_total",
+        "counter",
+        "Payload bytes sent to a connected peer",
+        network
+            .traffic
+            .iter()
+            .map(|(peer_id, traffic)| (format!("{{peer=\"{peer_id}\"}}"), traffic.bytes_out))
+            .collect(),
+    );
+    metric(
+        "blocks_fetched_total",
+        "counter",
+        "Timeline blocks fetched from peers",
+        vec![(String::new(), network.blocks_fetched)],
+    );
+    metric(
+        "blocks_served_total",
+        "counter",
+        "Blocks served to peers via bitswap",
+        vec![(String::new(), network.blocks_served)],
+    );
+    metric(
+        "gossip_messages_received_total",
+        "counter",
+        "Gossip messages received",
+        vec![(String::new(), network.gossip_messages_received)],
+    );
+    metric(
+        "gossip_messages_published_total",
+        "counter",
+        "Gossip messages published",
+        vec![(String::new(), network.gossip_messages_published)],
+    );
+    metric(
+        "blockstore_bytes",
+        "gauge",
+        "Size of the blockstore",
+        vec![(String::new(), report.blockstore_bytes)],
+    );
+    metric(
+        "posts_published_total",
+        "counter",
+        "Posts published by this node",
+        vec![(String::new(), report.posts_published)],
+    );
+    metric(
+        "timeline_nodes_fetched_total",
+        "counter",
+        "Timeline nodes of followed authors fetched from peers",
+        vec![(String::new(), report.nodes_fetched)],
+    );
+    metric(
+        "sync_lag_seconds",
+        "gauge",
+        "Seconds since the timeline of a followed author was last synchronized",
+        report
+            .sync_lag
+            .iter()
+            .map(|(author, lag)| (format!("{{author=\"{author}\"}}"), *lag))
+            .collect(),
+    );
+    metric(
+        "command_queue_depth",
+        "gauge",
+        "Commands waiting to be handled",
+        vec![(String::new(), report.command_queue_depth as u64)],
+    );
+
+    out
+}
+
+/// Answer the clients of the metrics endpoint with the latest report
+///
+/// Every client is answered in its own task, so a slow client does not hold up the others.
+pub(crate) async fn serve(
+    listener: tokio::net::TcpListener,
+    report: tokio::sync::watch::Receiver<MetricsReport>,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                debug!(?error, "Failed to accept metrics request");
+                continue;
+            }
+        };
+
+        let report = report.borrow().clone();
+        tokio::spawn(async move {
+            if let Err(error) = respond(stream, &report).await {
+                debug!(?error, "Failed to answer metrics request");
+            }
+        });
+    }
+}
+
+/// Answer a single HTTP request on the metrics endpoint
+async fn respond(mut stream: tokio::net::TcpStream, report: &MetricsReport) -> std::io::Result<()> {
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    let read_head = async {
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    tokio::time::timeout(REQUEST_TIMEOUT, read_head)
+        .await
+        .map_err(|_| std::io::ErrorKind::TimedOut)??;
+
+    let response = if request.starts_with(b"GET /metrics ") {
+        let body = render_prometheus(report);
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Counters of the application, as opposed to the network
+#[derive(Default)]
+pub(crate) struct AppMetrics {
+    posts_published: AtomicU64,
+    nodes_fetched: AtomicU64,
+    synced: Mutex<HashMap<String, std::time::Instant>>,
+}
+
+impl AppMetrics {
+    pub(crate) fn post_published(&self) {
+        self.posts_published.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The timeline of `author` was synchronized, fetching `nodes` nodes from peers
+    pub(crate) fn timeline_synced(&self, author: &str, nodes: u64) {
+        self.nodes_fetched.fetch_add(nodes, Ordering::Relaxed);
+        self.synced
+            .lock()
+            .unwrap()
+            .insert(author.to_string(), std::time::Instant::now());
+    }
+
+    pub(crate) fn forget_author(&self, author: &str) {
+        self.synced.lock().unwrap().remove(author);
+    }
+
+    pub(crate) fn fill_report(&self, report: &mut MetricsReport) {
+        report.posts_published = self.posts_published.load(Ordering::Relaxed);
+        report.nodes_fetched = self.nodes_fetched.load(Ordering::Relaxed);
+        report.sync_lag = self
+            .synced
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(author, synced)| (author.clone(), synced.elapsed().as_secs()))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connection_counts() {
+        let recorder = MetricsRecorder::default();
+        let peer = PeerId::random();
+
+        recorder.set_connections(peer, 2);
+        recorder.gossip_message_received();
+        recorder.peer_traffic(peer).received(10);
+        recorder.peer_traffic(peer).sent(3);
+        let metrics = recorder.snapshot().await;
+        assert_eq!(metrics.connection_count(), 2);
+        assert_eq!(metrics.gossip_messages_received, 1);
+        assert_eq!(
+            metrics.traffic[&peer],
+            Traffic {
+                bytes_in: 10,
+                bytes_out: 3
+            }
+        );
+
+        recorder.set_connections(peer, 0);
+        let metrics = recorder.snapshot().await;
+        assert!(metrics.connections.is_empty());
+        assert!(metrics.traffic.is_empty());
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let report = MetricsReport {
+            blockstore_bytes: 42,
+            sync_lag: BTreeMap::from([("author".to_string(), 7)]),
+            command_queue_depth: 3,
+            ..Default::default()
+        };
+
+        let text = render_prometheus(&report);
+        assert!(
+            text.contains("# TYPE distrox_blockstore_bytes gauge\ndistrox_blockstore_bytes 42\n")
+        );
+        assert!(text.contains("distrox_sync_lag_seconds{author=\"author\"} 7\n"));
+        assert!(text.contains("distrox_command_queue_depth 3\n"));
+    }
+}
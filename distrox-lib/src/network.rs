@@ -1,17 +1,36 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
+use futures::stream::BoxStream;
 use futures::Stream;
 use libipld::prelude::Codec;
 use rust_ipfs::Multiaddr;
 use tracing::trace;
 
+use distrox_types::account::Account;
 use distrox_types::node::Node;
 use distrox_types::post::Post;
 
 use crate::error::Error;
+use crate::event::Event;
+use crate::metrics::MetricsRecorder;
+use crate::metrics::NetworkMetrics;
 
+/// How many connection events are buffered, before a slow receiver misses some
+const CONNECTION_EVENT_CAPACITY: usize = 64;
+
+/// How long to wait for a block that is not in the local blockstore
+const FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+type UninitializedIpfs = rust_ipfs::UninitializedIpfs<network_behaviour::Behaviour>;
+
+#[derive(Clone)]
 pub struct Network {
     ipfs: rust_ipfs::Ipfs,
+    offline: bool,
+    blockstore_bytes: Arc<std::sync::atomic::AtomicU64>,
+    metrics: Arc<MetricsRecorder>,
+    connection_events: tokio::sync::broadcast::Sender<Event>,
 }
 
 impl Network {
@@ -19,42 +38,124 @@ impl Network {
         storage_path: PathBuf,
         bootstrap_nodes: BootstrapNodes,
         listening_addrs: ListeningAddrs,
+        discovery: &crate::configuration::Discovery,
+        keypair: libp2p::identity::Keypair,
+        offline: bool,
     ) -> Result<Self, Error> {
+        if offline {
+            return Self::load_offline(storage_path, keypair).await;
+        }
+
+        let metrics = Arc::new(MetricsRecorder::default());
+        let blockstore_bytes = Arc::default();
+        let mut ipfs = UninitializedIpfs::with_opt(rust_ipfs::IpfsOptions {
+            ipfs_path: blockstore::on_disk(storage_path, &blockstore_bytes).await?,
+            bootstrap: bootstrap_nodes.into(),
+            ..Default::default()
+        })
+        .set_keypair(keypair)
+        .add_listening_addrs(listening_addrs.into())
+        .enable_relay(true)
+        .enable_relay_server(None);
+
+        if discovery.mdns() {
+            ipfs = ipfs.enable_mdns();
+        }
+        if discovery.upnp() {
+            ipfs = ipfs.enable_upnp();
+        }
+
+        let (connection_events, _) = tokio::sync::broadcast::channel(CONNECTION_EVENT_CAPACITY);
+        let ipfs = with_metrics(ipfs, &metrics, &connection_events)
+            .start()
+            .await?;
+
+        Ok(Network {
+            ipfs,
+            offline: false,
+            blockstore_bytes,
+            metrics,
+            connection_events,
+        })
+    }
+
+    /// Open the blockstore without listening on any address or dialing any peer
+    async fn load_offline(
+        storage_path: PathBuf,
+        keypair: libp2p::identity::Keypair,
+    ) -> Result<Self, Error> {
+        let blockstore_bytes = Arc::default();
         let ipfs = rust_ipfs::UninitializedIpfs::<network_behaviour::Behaviour>::with_opt(
             rust_ipfs::IpfsOptions {
-                ipfs_path: rust_ipfs::StoragePath::Disk(storage_path),
-                bootstrap: bootstrap_nodes.into(),
+                ipfs_path: blockstore::on_disk(storage_path, &blockstore_bytes).await?,
+                listening_addrs: Vec::new(),
                 ..Default::default()
             },
         )
-        .add_listening_addrs(listening_addrs.into())
-        .enable_mdns()
-        .enable_relay(true)
-        .enable_relay_server(None)
-        .enable_upnp()
+        .set_keypair(keypair)
         .start()
         .await?;
 
-        Ok(Network { ipfs })
+        Ok(Network {
+            ipfs,
+            offline: true,
+            blockstore_bytes,
+            metrics: Arc::new(MetricsRecorder::default()),
+            connection_events: tokio::sync::broadcast::channel(CONNECTION_EVENT_CAPACITY).0,
+        })
     }
 
     #[cfg(test)]
-    async fn inmemory(listening_addrs: ListeningAddrs) -> Result<Self, Error> {
-        let ipfs = rust_ipfs::UninitializedIpfs::<network_behaviour::Behaviour>::with_opt(
-            rust_ipfs::IpfsOptions {
-                ipfs_path: rust_ipfs::StoragePath::Memory,
-                ..Default::default()
-            },
-        )
+    pub(crate) async fn inmemory(listening_addrs: ListeningAddrs) -> Result<Self, Error> {
+        let metrics = Arc::new(MetricsRecorder::default());
+        let blockstore_bytes = Arc::default();
+        let ipfs = UninitializedIpfs::with_opt(rust_ipfs::IpfsOptions {
+            ipfs_path: blockstore::in_memory(&blockstore_bytes),
+            ..Default::default()
+        })
         .add_listening_addrs(listening_addrs.into())
         .enable_mdns()
         .enable_relay(true)
         .enable_relay_server(None)
-        .enable_upnp()
-        .start()
-        .await?;
+        .enable_upnp();
+
+        let (connection_events, _) = tokio::sync::broadcast::channel(CONNECTION_EVENT_CAPACITY);
+        let ipfs = with_metrics(ipfs, &metrics, &connection_events)
+            .start()
+            .await?;
+
+        Ok(Network {
+            ipfs,
+            offline: false,
+            blockstore_bytes,
+            metrics,
+            connection_events,
+        })
+    }
+
+    /// How busy the node has been since it was started
+    pub async fn metrics(&self) -> NetworkMetrics {
+        self.metrics.snapshot().await
+    }
+
+    /// Receive `Event::ConnectionEstablished` and `Event::ConnectionClosed`, starting now
+    pub fn connection_events(&self) -> tokio::sync::broadcast::Receiver<Event> {
+        self.connection_events.subscribe()
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
 
-        Ok(Network { ipfs })
+    pub async fn connected_peers(&self) -> Result<Vec<libp2p::PeerId>, Error> {
+        self.ipfs.connected().await.map_err(Error::from)
+    }
+
+    pub fn local_peer_id(&self) -> Result<libp2p::PeerId, Error> {
+        self.ipfs
+            .keypair()
+            .map(|keypair| keypair.public().to_peer_id())
+            .map_err(Error::from)
     }
 
     pub async fn listening_addresses(&self) -> Result<Vec<Multiaddr>, Error> {
@@ -69,6 +170,45 @@ impl Network {
         self.ipfs.addrs().await.map_err(Error::from)
     }
 
+    /// Start listening on `addr`, returning the address that is actually listened on
+    pub async fn add_listening_address(&self, addr: Multiaddr) -> Result<Multiaddr, Error> {
+        if self.offline {
+            return Err(Error::Offline);
+        }
+
+        self.ipfs
+            .add_listening_address(addr)
+            .await
+            .map_err(Error::from)
+    }
+
+    pub async fn remove_listening_address(&self, addr: Multiaddr) -> Result<(), Error> {
+        if self.offline {
+            return Err(Error::Offline);
+        }
+
+        self.ipfs
+            .remove_listening_address(addr)
+            .await
+            .map_err(Error::from)
+    }
+
+    pub async fn add_bootstrap_node(&self, addr: Multiaddr) -> Result<(), Error> {
+        self.ipfs
+            .add_bootstrap(addr)
+            .await
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    pub async fn remove_bootstrap_node(&self, addr: Multiaddr) -> Result<(), Error> {
+        self.ipfs
+            .remove_bootstrap(addr)
+            .await
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
     pub async fn add_peer(&self, peer_id: libp2p::PeerId, addr: Multiaddr) -> Result<(), Error> {
         self.ipfs.add_peer(peer_id, addr).await.map_err(Error::from)
     }
@@ -78,6 +218,10 @@ impl Network {
         peer_id: libp2p::PeerId,
         addrs: Vec<Multiaddr>,
     ) -> Result<(), Error> {
+        if self.offline {
+            return Err(Error::Offline);
+        }
+
         let opts = libp2p::swarm::dial_opts::DialOpts::peer_id(peer_id)
             .condition(libp2p::swarm::dial_opts::PeerCondition::Disconnected)
             .addresses(addrs)
@@ -88,6 +232,10 @@ impl Network {
     }
 
     pub async fn connect_without_peer(&self, addr: Multiaddr) -> Result<(), Error> {
+        if self.offline {
+            return Err(Error::Offline);
+        }
+
         tracing::debug!(address = ?addr, "Connecting");
         let opts = libp2p::swarm::dial_opts::DialOpts::unknown_peer_id()
             .address(addr)
@@ -96,6 +244,57 @@ impl Network {
         self.ipfs.connect(opts).await.map_err(Error::from)
     }
 
+    /// Store `block` and announce in the DHT that we provide it
+    pub async fn provide(&self, block: rust_ipfs::Block) -> Result<(), Error> {
+        if self.offline {
+            return Err(Error::Offline);
+        }
+
+        let cid = self.ipfs.put_block(block).await?;
+        self.pin(cid, false).await?;
+        self.ipfs.provide(cid).await.map_err(Error::from)
+    }
+
+    /// Stop keeping a block we provided
+    ///
+    /// The DHT has no way to withdraw a provider record, it expires by itself once we stop
+    /// republishing it.
+    pub async fn stop_providing(&self, cid: cid::Cid) -> Result<(), Error> {
+        self.unpin(cid, false).await
+    }
+
+    /// Ask the DHT for peers providing `cid`, for at most `timeout`
+    pub async fn find_providers(
+        &self,
+        cid: cid::Cid,
+        timeout: std::time::Duration,
+    ) -> Result<Vec<libp2p::PeerId>, Error> {
+        use futures::StreamExt;
+
+        if self.offline {
+            return Err(Error::Offline);
+        }
+
+        let mut providers = Vec::new();
+        let mut stream = self.ipfs.get_providers(cid).await?;
+        let _ = tokio::time::timeout(timeout, async {
+            while let Some(peer_id) = stream.next().await {
+                providers.push(peer_id);
+            }
+        })
+        .await;
+        Ok(providers)
+    }
+
+    /// Find the addresses of a peer, using the DHT if we do not know them already
+    pub async fn find_peer(&self, peer_id: libp2p::PeerId) -> Result<Vec<Multiaddr>, Error> {
+        if self.offline {
+            return Err(Error::Offline);
+        }
+
+        self.ipfs.find_peer(peer_id).await.map_err(Error::from)
+    }
+
     pub async fn insert_node(&self, node: Node) -> Result<cid::Cid, Error> {
         // WHY???
         let ipld = libipld::cbor::DagCborCodec.encode(&node)?;
@@ -110,6 +309,13 @@ impl Network {
         self.ipfs.put_dag(ipld).await.map_err(Error::from)
     }
 
+    pub async fn insert_account(&self, account: Account) -> Result<cid::Cid, Error> {
+        // WHY???
+        let ipld = libipld::cbor::DagCborCodec.encode(&account)?;
+        let ipld: libipld::Ipld = libipld::cbor::DagCborCodec.decode(&ipld)?;
+        self.ipfs.put_dag(ipld).await.map_err(Error::from)
+    }
+
     pub async fn insert_blob(
         &self,
         blob: impl Stream<Item = u8> + Send,
@@ -161,12 +367,19 @@ impl Network {
     }
 
     async fn fetch_dag(&self, cid: cid::Cid) -> Result<libipld::Ipld, Error> {
-        self.ipfs
-            .get_dag(rust_ipfs::path::IpfsPath::new(
-                rust_ipfs::path::PathRoot::Ipld(cid),
-            ))
+        let local = self.ipfs.repo().get_block_now(&cid).await?.is_some();
+
+        let fetch = self.ipfs.get_dag(rust_ipfs::path::IpfsPath::new(
+            rust_ipfs::path::PathRoot::Ipld(cid),
+        ));
+        let ipld = tokio::time::timeout(FETCH_TIMEOUT, fetch)
             .await
-            .map_err(Error::from)
+            .map_err(|_| Error::FetchTimeout(cid))??;
+
+        if !local {
+            self.metrics.block_fetched();
+        }
+        Ok(ipld)
     }
 
     pub async fn get_post(&self, cid: cid::Cid) -> Result<Post, Error> {
@@ -186,6 +399,399 @@ impl Network {
                 .map_err(Error::from)
         })
     }
+
+    pub async fn get_account(&self, cid: cid::Cid) -> Result<Account, Error> {
+        self.fetch_dag(cid).await.and_then(|ipld| {
+            let bytes = libipld::cbor::DagCborCodec.encode(&ipld)?;
+            libipld::cbor::DagCborCodec
+                .decode(&bytes)
+                .map_err(Error::from)
+        })
+    }
+
+    /// Get a node only if it is in the local blockstore
+    pub async fn get_local_node(&self, cid: cid::Cid) -> Result<Option<Node>, Error> {
+        self.ipfs
+            .repo()
+            .get_block_now(&cid)
+            .await?
+            .map(|block| {
+                libipld::cbor::DagCborCodec
+                    .decode(block.data())
+                    .map_err(Error::from)
+            })
+            .transpose()
+    }
+
+    /// Get a post only if it is in the local blockstore
+    pub async fn get_local_post(&self, cid: cid::Cid) -> Result<Option<Post>, Error> {
+        self.ipfs
+            .repo()
+            .get_block_now(&cid)
+            .await?
+            .map(|block| {
+                libipld::cbor::DagCborCodec
+                    .decode(block.data())
+                    .map_err(Error::from)
+            })
+            .transpose()
+    }
+
+    /// Get the content of a blob only if it is in the local blockstore
+    pub async fn get_local_blob(&self, cid: cid::Cid) -> Result<Option<Vec<u8>>, Error> {
+        use futures::stream::TryStreamExt;
+
+        if self.ipfs.repo().get_block_now(&cid).await?.is_none() {
+            return Ok(None);
+        }
+
+        self.ipfs
+            .cat_unixfs(cid, None)
+            .await
+            .map_err(Error::ReadingBlob)?
+            .try_concat()
+            .await
+            .map(Some)
+            .map_err(Error::ReadingBlob)
+    }
+
+    /// The size of a single block in the local blockstore, zero if it is not there
+    pub async fn local_block_size(&self, cid: cid::Cid) -> Result<u64, Error> {
+        Ok(self
+            .ipfs
+            .repo()
+            .get_block_now(&cid)
+            .await?
+            .map(|block| block.data().len() as u64)
+            .unwrap_or_default())
+    }
+
+    /// The size of all blocks of the DAG rooted at `cid` that are in the local blockstore
+    pub async fn local_dag_size(&self, cid: cid::Cid) -> Result<u64, Error> {
+        let mut size = 0;
+        let mut seen = std::collections::HashSet::new();
+        let mut queue = vec![cid];
+
+        while let Some(cid) = queue.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+
+            if let Some(block) = self.ipfs.repo().get_block_now(&cid).await? {
+                size += block.data().len() as u64;
+                block.references(&mut queue)?;
+            }
+        }
+
+        Ok(size)
+    }
+
+    /// Pin `cid` directly or recursively, unless it is pinned recursively already
+    pub async fn pin(&self, cid: cid::Cid, recursive: bool) -> Result<(), Error> {
+        // The blockstore refuses to pin a recursively pinned cid again
+        let recursive_pin = Some(rust_ipfs::PinMode::Recursive);
+        if self.ipfs.query_pins(vec![cid], recursive_pin).await.is_ok() {
+            return Ok(());
+        }
+
+        self.ipfs
+            .insert_pin(&cid, recursive)
+            .await
+            .map_err(Error::from)
+    }
+
+    pub async fn is_pinned(&self, cid: cid::Cid) -> Result<bool, Error> {
+        self.ipfs.is_pinned(&cid).await.map_err(Error::from)
+    }
+
+    /// Remove a direct or recursive pin, if there is one
+    pub async fn unpin(&self, cid: cid::Cid, recursive: bool) -> Result<(), Error> {
+        let mode = if recursive {
+            rust_ipfs::PinMode::Recursive
+        } else {
+            rust_ipfs::PinMode::Direct
+        };
+
+        // Querying fails if the cid is not pinned in the requested mode
+        if self.ipfs.query_pins(vec![cid], Some(mode)).await.is_err() {
+            return Ok(());
+        }
+
+        self.ipfs
+            .remove_pin(&cid, recursive)
+            .await
+            .map_err(Error::from)
+    }
+
+    /// Remove all unpinned blocks from the blockstore
+    pub async fn gc(&self) -> Result<GcReport, Error> {
+        self.evict_unpinned(u64::MAX).await
+    }
+
+    /// Remove unpinned blocks from the blockstore until at least `bytes` are freed
+    pub async fn evict_unpinned(&self, bytes: u64) -> Result<GcReport, Error> {
+        let mut report = GcReport::default();
+
+        for cid in self.ipfs.repo().list_blocks().await? {
+            if report.freed_bytes >= bytes {
+                break;
+            }
+
+            if self.ipfs.is_pinned(&cid).await? {
+                continue;
+            }
+
+            let Some(block) = self.ipfs.repo().get_block_now(&cid).await? else {
+                continue;
+            };
+
+            if self.ipfs.remove_block(cid).await.is_ok() {
+                report.removed_blocks += 1;
+                report.freed_bytes += block.data().len() as u64;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// The size of all blocks in the blockstore
+    pub fn blockstore_size(&self) -> u64 {
+        self.blockstore_bytes
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// How the blockstore is used
+    ///
+    /// Everything reachable from `own_roots` counts as own content. Of the rest, pinned blocks
+    /// are content of followed authors and unpinned blocks are cache.
+    pub async fn storage_usage(&self, own_roots: &[cid::Cid]) -> Result<StorageUsage, Error> {
+        let mut own_blocks = std::collections::HashSet::new();
+        let mut queue = own_roots.to_vec();
+        while let Some(cid) = queue.pop() {
+            if !own_blocks.insert(cid) {
+                continue;
+            }
+
+            if let Some(block) = self.ipfs.repo().get_block_now(&cid).await? {
+                block.references(&mut queue)?;
+            }
+        }
+
+        let mut usage = StorageUsage::default();
+        for cid in self.ipfs.repo().list_blocks().await? {
+            let Some(block) = self.ipfs.repo().get_block_now(&cid).await? else {
+                continue;
+            };
+            let size = block.data().len() as u64;
+
+            if own_blocks.contains(&cid) {
+                usage.own_bytes += size;
+            } else if self.ipfs.is_pinned(&cid).await? {
+                usage.followed_bytes += size;
+            } else {
+                usage.cache_bytes += size;
+            }
+        }
+
+        Ok(usage)
+    }
+
+    /// Write everything reachable from `roots` into a CAR file, fetching missing blocks
+    ///
+    /// Returns the number of blocks written. Fails if a missing block cannot be fetched within
+    /// `FETCH_TIMEOUT`.
+    pub async fn export_car<W>(&self, roots: &[cid::Cid], writer: W) -> Result<usize, Error>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let mut car = crate::car::CarWriter::new(writer, roots).await?;
+        let mut seen = std::collections::HashSet::new();
+        let mut queue = roots.to_vec();
+
+        while let Some(cid) = queue.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+
+            let block = tokio::time::timeout(FETCH_TIMEOUT, self.ipfs.get_block(&cid))
+                .await
+                .map_err(|_| Error::FetchTimeout(cid))??;
+            block.references(&mut queue)?;
+            car.write_block(&cid, block.data()).await?;
+        }
+
+        car.finish().await?;
+        Ok(seen.len())
+    }
+
+    /// Import all blocks of a CAR file into the blockstore and pin its roots
+    ///
+    /// Every block is checked against its CID before it is stored. The roots are only pinned if
+    /// everything reachable from them is in the blockstore afterwards, pinning would otherwise
+    /// fetch the missing blocks from the network.
+    pub async fn import_car<R>(&self, reader: R) -> Result<CarImport, Error>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        let mut car = crate::car::CarReader::new(reader).await?;
+        let mut blocks = 0;
+
+        while let Some((cid, data)) = car.next_block().await? {
+            let block =
+                rust_ipfs::Block::new(cid, data).map_err(|_| Error::CarCidMismatch { cid })?;
+            self.ipfs.put_block(block).await?;
+            blocks += 1;
+        }
+
+        let roots = car.roots().to_vec();
+        if let Some(missing) = self.first_missing_block(&roots).await? {
+            return Err(Error::IncompleteCar { missing });
+        }
+        for root in roots.iter() {
+            self.pin(*root, true).await?;
+        }
+
+        Ok(CarImport { roots, blocks })
+    }
+
+    /// The first block reachable from `roots` that is not in the local blockstore, if any
+    async fn first_missing_block(&self, roots: &[cid::Cid]) -> Result<Option<cid::Cid>, Error> {
+        let mut seen = std::collections::HashSet::new();
+        let mut queue = roots.to_vec();
+
+        while let Some(cid) = queue.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+
+            match self.ipfs.repo().get_block_now(&cid).await? {
+                Some(block) => block.references(&mut queue)?,
+                None => return Ok(Some(cid)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn subscribe(
+        &self,
+        topic: String,
+    ) -> Result<BoxStream<'static, libp2p::gossipsub::Message>, Error> {
+        use futures::StreamExt;
+
+        let metrics = self.metrics.clone();
+        let messages = self.ipfs.pubsub_subscribe(topic).await?;
+        Ok(messages
+            .inspect(move |_| metrics.gossip_message_received())
+            .boxed())
+    }
+
+    pub async fn subscription_events(
+        &self,
+        topic: &str,
+    ) -> Result<futures::stream::BoxStream<'static, rust_ipfs::PubsubEvent>, Error> {
+        self.ipfs.pubsub_events(topic).await.map_err(Error::from)
+    }
+
+    pub async fn unsubscribe(&self, topic: &str) -> Result<(), Error> {
+        self.ipfs
+            .pubsub_unsubscribe(topic)
+            .await
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    pub async fn publish(&self, topic: String, data: Vec<u8>) -> Result<(), Error> {
+        if self.offline {
+            return Err(Error::Offline);
+        }
+
+        self.ipfs.pubsub_publish(topic, data).await?;
+        self.metrics.gossip_message_published();
+        Ok(())
+    }
+}
+
+/// Count connections, traffic and served blocks into `metrics`, and report opened and closed
+/// connections to `connection_events`
+fn with_metrics(
+    ipfs: UninitializedIpfs,
+    metrics: &Arc<MetricsRecorder>,
+    connection_events: &tokio::sync::broadcast::Sender<Event>,
+) -> UninitializedIpfs {
+    let transport_metrics = metrics.clone();
+    let swarm_metrics = metrics.clone();
+    let connection_events = connection_events.clone();
+
+    ipfs.set_custom_transport(Box::new(move |keypair, relay| {
+        let (transport, bandwidth) = transport::build(keypair, relay, transport_metrics.clone())?;
+        let _ = transport_metrics.bandwidth.set(bandwidth);
+        Ok(transport)
+    }))
+    .swarm_events(move |swarm, event| {
+        if swarm_metrics.bitswap_server.get().is_none() {
+            if let Some(server) = swarm
+                .behaviour()
+                .bitswap
+                .as_ref()
+                .and_then(|bitswap| bitswap.server())
+            {
+                let _ = swarm_metrics.bitswap_server.set(server.clone());
+            }
+        }
+
+        match event {
+            libp2p::swarm::SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                swarm_metrics.set_connections(*peer_id, num_established.get());
+                let address = endpoint.get_remote_address().clone();
+                let _ = connection_events.send(Event::ConnectionEstablished { address });
+            }
+            libp2p::swarm::SwarmEvent::ConnectionClosed {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                swarm_metrics.set_connections(*peer_id, *num_established);
+                let address = endpoint.get_remote_address().clone();
+                let _ = connection_events.send(Event::ConnectionClosed { address });
+            }
+            _ => {}
+        }
+    })
+}
+
+/// What a garbage collection run removed from the blockstore
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GcReport {
+    pub removed_blocks: usize,
+    pub freed_bytes: u64,
+}
+
+/// What was read from a CAR file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarImport {
+    pub roots: Vec<cid::Cid>,
+    pub blocks: usize,
+}
+
+/// Size of the blockstore, by kind of content
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub own_bytes: u64,
+    pub followed_bytes: u64,
+    pub cache_bytes: u64,
+}
+
+impl StorageUsage {
+    pub fn total_bytes(&self) -> u64 {
+        self.own_bytes + self.followed_bytes + self.cache_bytes
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -206,6 +812,306 @@ impl From<ListeningAddrs> for Vec<Multiaddr> {
     }
 }
 
+/// The blockstores rust-ipfs would use, keeping a running total of the size of all blocks
+mod blockstore {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use cid::Cid;
+    use rust_ipfs::repo::blockstore::flatfs::FsBlockStore;
+    use rust_ipfs::repo::datastore::flatfs::FsDataStore;
+    use rust_ipfs::repo::lock::FsLock;
+    use rust_ipfs::repo::{BlockPut, BlockRm, BlockRmError, BlockStore};
+    use rust_ipfs::{Block, StoragePath};
+
+    use crate::error::Error;
+
+    pub(super) async fn on_disk(
+        path: PathBuf,
+        bytes: &Arc<AtomicU64>,
+    ) -> Result<StoragePath, Error> {
+        tokio::fs::create_dir_all(&path)
+            .await
+            .map_err(Error::CreatingDirectory)?;
+
+        Ok(StoragePath::Custom {
+            blockstore: Arc::new(CountingBlockStore {
+                inner: FsBlockStore::new(path.join("blockstore")),
+                bytes: bytes.clone(),
+            }),
+            datastore: Arc::new(FsDataStore::new(path.join("datastore"))),
+            lock: Arc::new(FsLock::new(path.join("repo_lock"))),
+        })
+    }
+
+    #[cfg(test)]
+    pub(super) fn in_memory(bytes: &Arc<AtomicU64>) -> StoragePath {
+        use rust_ipfs::repo::blockstore::memory::MemBlockStore;
+        use rust_ipfs::repo::datastore::memory::MemDataStore;
+        use rust_ipfs::repo::lock::MemLock;
+
+        StoragePath::Custom {
+            blockstore: Arc::new(CountingBlockStore {
+                inner: MemBlockStore::new(Default::default()),
+                bytes: bytes.clone(),
+            }),
+            datastore: Arc::new(MemDataStore::new(Default::default())),
+            lock: Arc::new(MemLock),
+        }
+    }
+
+    /// Counts the bytes of the blocks put into and removed from the inner store
+    ///
+    /// The blocks that are already stored are counted once, when the store is initialized.
+    #[derive(Debug)]
+    struct CountingBlockStore<S> {
+        inner: S,
+        bytes: Arc<AtomicU64>,
+    }
+
+    impl<S> CountingBlockStore<S> {
+        fn add(&self, size: u64) {
+            self.bytes.fetch_add(size, Ordering::Relaxed);
+        }
+
+        fn subtract(&self, size: u64) {
+            let _ = self
+                .bytes
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+                    Some(bytes.saturating_sub(size))
+                });
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<S: BlockStore> BlockStore for CountingBlockStore<S> {
+        async fn init(&self) -> Result<(), rust_ipfs::Error> {
+            self.inner.init().await?;
+
+            self.bytes.store(0, Ordering::Relaxed);
+            for cid in self.inner.list().await? {
+                if let Some(block) = self.inner.get(&cid).await? {
+                    self.add(block.data().len() as u64);
+                }
+            }
+            Ok(())
+        }
+
+        async fn open(&self) -> Result<(), rust_ipfs::Error> {
+            self.inner.open().await
+        }
+
+        async fn contains(&self, cid: &Cid) -> Result<bool, rust_ipfs::Error> {
+            self.inner.contains(cid).await
+        }
+
+        async fn get(&self, cid: &Cid) -> Result<Option<Block>, rust_ipfs::Error> {
+            self.inner.get(cid).await
+        }
+
+        async fn put(&self, block: Block) -> Result<(Cid, BlockPut), rust_ipfs::Error> {
+            let size = block.data().len() as u64;
+            let (cid, put) = self.inner.put(block).await?;
+            if let BlockPut::NewBlock = put {
+                self.add(size);
+            }
+            Ok((cid, put))
+        }
+
+        async fn remove(
+            &self,
+            cid: &Cid,
+        ) -> Result<Result<BlockRm, BlockRmError>, rust_ipfs::Error> {
+            let block = self.inner.get(cid).await?;
+            let removed = self.inner.remove(cid).await?;
+            if let (Ok(BlockRm::Removed(_)), Some(block)) = (&removed, block) {
+                self.subtract(block.data().len() as u64);
+            }
+            Ok(removed)
+        }
+
+        async fn list(&self) -> Result<Vec<Cid>, rust_ipfs::Error> {
+            self.inner.list().await
+        }
+
+        async fn wipe(&self) {
+            self.inner.wipe().await;
+            self.bytes.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The transport rust-ipfs would build by default, with bandwidth logging and per-peer traffic
+/// counting added
+mod transport {
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures::{ready, AsyncRead, AsyncWrite};
+    use libp2p::bandwidth::BandwidthSinks;
+    use libp2p::core::muxing::{
+        StreamMuxer, StreamMuxerBox, StreamMuxerEvent, StreamMuxerExt, SubstreamBox,
+    };
+    use libp2p::core::transport::timeout::TransportTimeout;
+    use libp2p::core::transport::upgrade::Version;
+    use libp2p::core::transport::{Boxed, OrTransport};
+    use libp2p::dns::{ResolverConfig, TokioDnsConfig};
+    use libp2p::{PeerId, Transport, TransportExt};
+
+    use crate::metrics::{MetricsRecorder, PeerTraffic};
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// The yamux buffer and window sizes of rust-ipfs
+    const YAMUX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+    const YAMUX_WINDOW_SIZE: u32 = 16 * 1024 * 1024;
+
+    type BoxedTransport = Boxed<(PeerId, StreamMuxerBox)>;
+
+    pub(super) fn build(
+        keypair: &libp2p::identity::Keypair,
+        relay: Option<libp2p::relay::client::Transport>,
+        metrics: Arc<MetricsRecorder>,
+    ) -> std::io::Result<(BoxedTransport, Arc<BandwidthSinks>)> {
+        let noise = libp2p::noise::Config::new(keypair)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        let mut yamux = libp2p::yamux::Config::default();
+        yamux.set_max_buffer_size(YAMUX_BUFFER_SIZE);
+        yamux.set_receive_window_size(YAMUX_WINDOW_SIZE);
+        yamux.set_window_update_mode(libp2p::yamux::WindowUpdateMode::on_receive());
+
+        let tcp_config = libp2p::tcp::Config::default()
+            .nodelay(true)
+            .port_reuse(true);
+        let tcp = libp2p::tcp::tokio::Transport::new(tcp_config.clone());
+        let ws = libp2p::websocket::WsConfig::new(libp2p::tcp::tokio::Transport::new(tcp_config));
+
+        let transport = TransportTimeout::new(tcp.or_transport(ws), TIMEOUT);
+        let transport =
+            TokioDnsConfig::custom(transport, ResolverConfig::cloudflare(), Default::default())?;
+
+        let transport = match relay {
+            Some(relay) => OrTransport::new(relay, transport)
+                .upgrade(Version::V1)
+                .authenticate(noise)
+                .multiplex(yamux)
+                .timeout(TIMEOUT)
+                .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+                .boxed(),
+            None => transport
+                .upgrade(Version::V1)
+                .authenticate(noise)
+                .multiplex(yamux)
+                .timeout(TIMEOUT)
+                .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+                .boxed(),
+        };
+
+        let transport = transport
+            .map(move |(peer_id, muxer), _| {
+                let muxer = CountingMuxer {
+                    inner: muxer,
+                    traffic: metrics.peer_traffic(peer_id),
+                };
+                (peer_id, StreamMuxerBox::new(muxer))
+            })
+            .boxed();
+
+        Ok(transport.with_bandwidth_logging())
+    }
+
+    /// Counts the traffic on the substreams of a connection into the counters of its peer
+    struct CountingMuxer {
+        inner: StreamMuxerBox,
+        traffic: Arc<PeerTraffic>,
+    }
+
+    impl CountingMuxer {
+        fn substream(&self, inner: SubstreamBox) -> CountingSubstream {
+            CountingSubstream {
+                inner,
+                traffic: self.traffic.clone(),
+            }
+        }
+    }
+
+    impl StreamMuxer for CountingMuxer {
+        type Substream = CountingSubstream;
+        type Error = std::io::Error;
+
+        fn poll_inbound(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<Self::Substream, Self::Error>> {
+            let inner = ready!(self.inner.poll_inbound_unpin(cx))?;
+            Poll::Ready(Ok(self.substream(inner)))
+        }
+
+        fn poll_outbound(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<Self::Substream, Self::Error>> {
+            let inner = ready!(self.inner.poll_outbound_unpin(cx))?;
+            Poll::Ready(Ok(self.substream(inner)))
+        }
+
+        fn poll_close(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_close_unpin(cx)
+        }
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+            self.inner.poll_unpin(cx)
+        }
+    }
+
+    struct CountingSubstream {
+        inner: SubstreamBox,
+        traffic: Arc<PeerTraffic>,
+    }
+
+    impl AsyncRead for CountingSubstream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let read = ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+            self.traffic.received(read);
+            Poll::Ready(Ok(read))
+        }
+    }
+
+    impl AsyncWrite for CountingSubstream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+            self.traffic.sent(written);
+            Poll::Ready(Ok(written))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_close(cx)
+        }
+    }
+}
+
 mod network_behaviour {
     use std::task::{Context, Poll};
 
@@ -331,6 +1237,30 @@ mod tests {
         assert_eq!(received_node, node);
     }
 
+    #[tokio::test]
+    async fn test_blockstore_size() {
+        let listening_addr = ListeningAddrs(vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]);
+        let network = Network::inmemory(listening_addr).await.unwrap();
+        assert_eq!(network.blockstore_size(), 0);
+
+        let node = Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents: Vec::new(),
+            post: None,
+        };
+        let cid = network.insert_node(node.clone()).await.unwrap();
+        let size = network.local_block_size(cid).await.unwrap();
+        assert!(size > 0);
+        assert_eq!(network.blockstore_size(), size);
+
+        network.insert_node(node).await.unwrap();
+        assert_eq!(network.blockstore_size(), size);
+
+        let report = network.gc().await.unwrap();
+        assert_eq!(report.freed_bytes, size);
+        assert_eq!(network.blockstore_size(), 0);
+    }
+
     #[tokio::test]
     async fn test_connected_nodes() {
         let _ = env_logger::try_init();
@@ -349,6 +1279,7 @@ mod tests {
         let node2_addrs = node2.listening_addresses().await.unwrap();
         debug!("Node2 listens: {:?}", node2_addrs);
 
+        let mut connection_events = node2.connection_events();
         for addr in node1_addrs {
             node2.connect_without_peer(addr).await.unwrap();
         }
@@ -366,5 +1297,164 @@ mod tests {
         info!(?received_node, "Received object from node2");
 
         assert_eq!(received_node, node);
+
+        assert!(matches!(
+            connection_events.recv().await.unwrap(),
+            Event::ConnectionEstablished { .. }
+        ));
+
+        let node1_id = node1.local_peer_id().unwrap();
+        let traffic = node2.metrics().await.traffic[&node1_id];
+        assert!(traffic.bytes_in > 0 && traffic.bytes_out > 0);
+    }
+
+    #[tokio::test]
+    async fn test_gc_keeps_pinned() {
+        let _ = env_logger::try_init();
+        let listening_addr = ListeningAddrs(vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()]);
+        let node1 = Network::inmemory(listening_addr).await.unwrap();
+
+        let root = Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents: Vec::new(),
+            post: None,
+        };
+        let root_cid = node1.insert_node(root).await.unwrap();
+
+        let child = Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents: vec![root_cid],
+            post: None,
+        };
+        let child_cid = node1.insert_node(child.clone()).await.unwrap();
+
+        let unpinned = Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(1),
+            parents: Vec::new(),
+            post: None,
+        };
+        let unpinned_cid = node1.insert_node(unpinned).await.unwrap();
+
+        node1.pin(child_cid, true).await.unwrap();
+        let report = node1.gc().await.unwrap();
+        info!(?report, "Collected garbage");
+
+        assert_eq!(report.removed_blocks, 1);
+        assert!(report.freed_bytes > 0);
+        assert_eq!(node1.get_local_node(child_cid).await.unwrap(), Some(child));
+        assert!(node1.get_local_node(root_cid).await.unwrap().is_some());
+        assert!(node1.get_local_node(unpinned_cid).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_storage_usage() {
+        let _ = env_logger::try_init();
+        let listening_addr = ListeningAddrs(vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()]);
+        let node1 = Network::inmemory(listening_addr).await.unwrap();
+
+        let node = |version| Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(version),
+            parents: Vec::new(),
+            post: None,
+        };
+
+        let own = node1.insert_node(node(0)).await.unwrap();
+        let followed = node1.insert_node(node(1)).await.unwrap();
+        let cached = node1.insert_node(node(2)).await.unwrap();
+
+        node1.pin(own, true).await.unwrap();
+        node1.pin(followed, false).await.unwrap();
+
+        let usage = node1.storage_usage(&[own]).await.unwrap();
+        info!(?usage, "Storage usage");
+
+        assert_eq!(usage.own_bytes, node1.local_block_size(own).await.unwrap());
+        assert_eq!(
+            usage.followed_bytes,
+            node1.local_block_size(followed).await.unwrap()
+        );
+        assert_eq!(
+            usage.cache_bytes,
+            node1.local_block_size(cached).await.unwrap()
+        );
+
+        let report = node1.evict_unpinned(1).await.unwrap();
+        assert_eq!(report.removed_blocks, 1);
+        assert_eq!(node1.storage_usage(&[own]).await.unwrap().cache_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_car_export_import() {
+        let _ = env_logger::try_init();
+        let listening_addr = ListeningAddrs(vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()]);
+        let (node1, node2) = tokio::try_join!(
+            Network::inmemory(listening_addr.clone()),
+            Network::inmemory(listening_addr)
+        )
+        .unwrap();
+
+        let root = Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents: Vec::new(),
+            post: None,
+        };
+        let root_cid = node1.insert_node(root.clone()).await.unwrap();
+
+        let head = Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents: vec![root_cid],
+            post: None,
+        };
+        let head_cid = node1.insert_node(head.clone()).await.unwrap();
+
+        let mut car = Vec::new();
+        let exported = node1.export_car(&[head_cid], &mut car).await.unwrap();
+        assert_eq!(exported, 2);
+
+        let import = node2.import_car(car.as_slice()).await.unwrap();
+        assert_eq!(import.roots, vec![head_cid]);
+        assert_eq!(import.blocks, 2);
+        assert_eq!(node2.get_local_node(head_cid).await.unwrap(), Some(head));
+        assert_eq!(node2.get_local_node(root_cid).await.unwrap(), Some(root));
+    }
+
+    #[tokio::test]
+    async fn test_car_import_incomplete() {
+        let _ = env_logger::try_init();
+        let listening_addr = ListeningAddrs(vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()]);
+        let (node1, node2) = tokio::try_join!(
+            Network::inmemory(listening_addr.clone()),
+            Network::inmemory(listening_addr)
+        )
+        .unwrap();
+
+        let root = Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents: Vec::new(),
+            post: None,
+        };
+        let root_cid = node1.insert_node(root).await.unwrap();
+        let head = Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents: vec![root_cid],
+            post: None,
+        };
+        let head_cid = node1.insert_node(head).await.unwrap();
+
+        // The head without its parent
+        let block = node1.ipfs.repo().get_block_now(&head_cid).await.unwrap();
+        let mut car = crate::car::CarWriter::new(Vec::new(), &[head_cid])
+            .await
+            .unwrap();
+        car.write_block(&head_cid, block.unwrap().data())
+            .await
+            .unwrap();
+        let car = car.finish().await.unwrap();
+
+        let result = node2.import_car(car.as_slice()).await;
+        assert!(
+            matches!(result, Err(Error::IncompleteCar { missing }) if missing == root_cid),
+            "{result:?}"
+        );
     }
 }
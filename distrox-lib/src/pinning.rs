@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use distrox_types::post::Post;
+use tracing::debug;
+
+use crate::configuration::Pinning;
+use crate::error::Error;
+use crate::network::Network;
+
+/// Limits for keeping content of a followed author
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinningPolicy {
+    max_age: Option<time::Duration>,
+    max_bytes: Option<u64>,
+}
+
+impl PinningPolicy {
+    /// A policy that keeps nothing, used for authors we do not follow (anymore)
+    pub fn nothing() -> Self {
+        PinningPolicy {
+            max_age: None,
+            max_bytes: Some(0),
+        }
+    }
+
+    fn is_young_enough(&self, age: Option<time::Duration>) -> bool {
+        match (self.max_age, age) {
+            (Some(max_age), Some(age)) => age <= max_age,
+            _ => true,
+        }
+    }
+
+    /// Whether there is room for more content, after `used_bytes` are already kept
+    fn admits(&self, age: Option<time::Duration>, used_bytes: u64) -> bool {
+        self.is_young_enough(age)
+            && self
+                .max_bytes
+                .map(|max_bytes| used_bytes < max_bytes)
+                .unwrap_or(true)
+    }
+
+    /// Whether keeping `used_bytes` in total is within the limits
+    fn keeps(&self, age: Option<time::Duration>, used_bytes: u64) -> bool {
+        self.is_young_enough(age)
+            && self
+                .max_bytes
+                .map(|max_bytes| used_bytes <= max_bytes)
+                .unwrap_or(true)
+    }
+}
+
+impl From<&Pinning> for PinningPolicy {
+    fn from(config: &Pinning) -> Self {
+        PinningPolicy {
+            max_age: config
+                .followed_max_age_days()
+                .map(|days| time::Duration::days(days as i64)),
+            max_bytes: config.followed_max_bytes(),
+        }
+    }
+}
+
+/// Pin the nodes, posts and content of a followed timeline as far as the policy allows, unpin
+/// everything beyond
+///
+/// The timeline is walked from the head towards its roots. As long as the policy allows keeping
+/// content, missing blocks are fetched from the network. Beyond that point, only blocks that are
+/// in the local blockstore are visited, to remove their pins. A policy that keeps nothing never
+/// fetches anything.
+///
+/// Pins are not counted, so blocks that are also in the local history of one of the timelines at
+/// `others` keep their pins. Those timelines decide about them.
+///
+/// Returns how many nodes were fetched from the network.
+pub async fn apply_to_timeline(
+    network: &Network,
+    head: cid::Cid,
+    policy: &PinningPolicy,
+    others: &[cid::Cid],
+) -> Result<u64, Error> {
+    let now = time::OffsetDateTime::now_utc();
+    let mut fetched_nodes = 0;
+    let mut used_bytes = 0;
+    let mut keeping = policy.admits(None, 0);
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([head]);
+    // Only collected once something is unpinned
+    let mut shared = None;
+
+    while let Some(node_id) = queue.pop_front() {
+        if !seen.insert(node_id) {
+            continue;
+        }
+
+        let node = match network.get_local_node(node_id).await? {
+            Some(node) => Some(node),
+            None if keeping => {
+                fetched_nodes += 1;
+                Some(network.get_node(node_id).await?)
+            }
+            None => None,
+        };
+
+        let Some(node) = node else {
+            continue;
+        };
+        queue.extend(node.parents.iter().cloned());
+
+        let post = match node.post {
+            Some(post_id) if keeping => Some((post_id, network.get_post(post_id).await?)),
+            Some(post_id) => network
+                .get_local_post(post_id)
+                .await?
+                .map(|post| (post_id, post)),
+            None => None,
+        };
+
+        let (age, content) = match post.as_ref() {
+            Some((_, Post::Original(original))) => {
+                (Some(now - original.timestamp.0), Some(original.content))
+            }
+            _ => (None, None),
+        };
+
+        let mut keep = keeping && policy.admits(age, used_bytes);
+        if keep {
+            network.pin(node_id, false).await?;
+            if let Some((post_id, _)) = post.as_ref() {
+                network.pin(*post_id, false).await?;
+            }
+            if let Some(content) = content {
+                network.pin(content, true).await?;
+            }
+
+            let size = timeline_entry_size(network, node_id, post.as_ref(), content).await?;
+            keep = policy.keeps(age, used_bytes + size);
+            used_bytes += size;
+        }
+
+        if !keep {
+            debug!(%node_id, "Node is outside of pinning policy");
+            keeping = false;
+
+            if shared.is_none() {
+                shared = Some(local_history_blocks(network, others).await?);
+            }
+            let shared = shared.as_ref().expect("collected above");
+
+            if !shared.contains(&node_id) {
+                network.unpin(node_id, false).await?;
+            }
+            if let Some((post_id, _)) = post.as_ref().filter(|(id, _)| !shared.contains(id)) {
+                network.unpin(*post_id, false).await?;
+            }
+            if let Some(content) = content.filter(|content| !shared.contains(content)) {
+                network.unpin(content, true).await?;
+            }
+        }
+    }
+
+    Ok(fetched_nodes)
+}
+
+/// The nodes, posts and content in the local history of `heads`
+async fn local_history_blocks(
+    network: &Network,
+    heads: &[cid::Cid],
+) -> Result<HashSet<cid::Cid>, Error> {
+    let mut blocks = HashSet::new();
+    let mut queue = heads.iter().cloned().collect::<VecDeque<_>>();
+
+    while let Some(node_id) = queue.pop_front() {
+        if !blocks.insert(node_id) {
+            continue;
+        }
+        let Some(node) = network.get_local_node(node_id).await? else {
+            continue;
+        };
+        queue.extend(node.parents.iter().cloned());
+
+        let Some(post_id) = node.post else {
+            continue;
+        };
+        blocks.insert(post_id);
+        if let Some(Post::Original(original)) = network.get_local_post(post_id).await? {
+            blocks.insert(original.content);
+        }
+    }
+
+    Ok(blocks)
+}
+
+/// How long applying the pinning policy to a followed timeline in the background may take
+const FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// The outcome of `apply_to_timeline` for a followed author, run by `TimelineFetches`
+pub(crate) struct FetchedTimeline {
+    pub author: String,
+    pub head: cid::Cid,
+    pub result: Result<u64, Error>,
+}
+
+/// Followed timelines being fetched in the background, at most one per author
+pub(crate) struct TimelineFetches {
+    sender: tokio::sync::mpsc::UnboundedSender<FetchedTimeline>,
+    running: HashMap<String, (cid::Cid, tokio::task::JoinHandle<()>)>,
+}
+
+impl TimelineFetches {
+    pub fn new() -> (Self, tokio::sync::mpsc::UnboundedReceiver<FetchedTimeline>) {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let fetches = TimelineFetches {
+            sender,
+            running: HashMap::new(),
+        };
+        (fetches, receiver)
+    }
+
+    /// Apply `policy` to the timeline of `author` at `head`, the outcome is received from the
+    /// receiver returned by `new`
+    ///
+    /// A fetch of an older head of the same author is cancelled, the new head contains its
+    /// history. `others` are the heads of the other timelines we keep, see `apply_to_timeline`.
+    pub fn start(
+        &mut self,
+        network: &Network,
+        author: String,
+        head: cid::Cid,
+        policy: PinningPolicy,
+        others: Vec<cid::Cid>,
+    ) {
+        if let Some((running_head, task)) = self.running.get(&author) {
+            if *running_head == head {
+                return;
+            }
+            task.abort();
+        }
+
+        let network = network.clone();
+        let sender = self.sender.clone();
+        let task_author = author.clone();
+        let task = tokio::spawn(async move {
+            let apply = apply_to_timeline(&network, head, &policy, &others);
+            let result = tokio::time::timeout(FETCH_TIMEOUT, apply)
+                .await
+                .unwrap_or(Err(Error::FetchTimeout(head)));
+            let _ = sender.send(FetchedTimeline {
+                author: task_author,
+                head,
+                result,
+            });
+        });
+        self.running.insert(author, (head, task));
+    }
+
+    /// Forget the fetch that produced `fetched`
+    pub fn finished(&mut self, fetched: &FetchedTimeline) {
+        if self
+            .running
+            .get(&fetched.author)
+            .map(|(head, _)| *head == fetched.head)
+            .unwrap_or(false)
+        {
+            self.running.remove(&fetched.author);
+        }
+    }
+}
+
+impl Drop for TimelineFetches {
+    fn drop(&mut self) {
+        for (_, task) in self.running.values() {
+            task.abort();
+        }
+    }
+}
+
+async fn timeline_entry_size(
+    network: &Network,
+    node_id: cid::Cid,
+    post: Option<&(cid::Cid, Post)>,
+    content: Option<cid::Cid>,
+) -> Result<u64, Error> {
+    let mut size = network.local_block_size(node_id).await?;
+    if let Some((post_id, _)) = post {
+        size += network.local_block_size(*post_id).await?;
+    }
+    if let Some(content) = content {
+        size += network.local_dag_size(content).await?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_limits() {
+        let policy = PinningPolicy {
+            max_age: Some(time::Duration::days(7)),
+            max_bytes: Some(100),
+        };
+
+        assert!(policy.admits(Some(time::Duration::days(1)), 50));
+        assert!(!policy.admits(None, 100));
+        assert!(policy.keeps(None, 100));
+        assert!(!policy.keeps(Some(time::Duration::days(8)), 50));
+        assert!(!policy.keeps(Some(time::Duration::days(1)), 101));
+        assert!(!PinningPolicy::nothing().admits(None, 0));
+    }
+
+    #[tokio::test]
+    async fn test_nothing_policy_does_not_fetch() {
+        let _ = env_logger::try_init();
+        let listening_addr =
+            crate::network::ListeningAddrs(vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]);
+        let network = Network::inmemory(listening_addr).await.unwrap();
+
+        // Not in the local blockstore, and no peer to fetch it from
+        let missing =
+            cid::Cid::try_from("bafyreigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi")
+                .unwrap();
+        let fetched = apply_to_timeline(&network, missing, &PinningPolicy::nothing(), &[])
+            .await
+            .unwrap();
+        assert_eq!(fetched, 0);
+    }
+
+    #[tokio::test]
+    async fn test_shared_content_keeps_pin() {
+        let _ = env_logger::try_init();
+        let listening_addr =
+            crate::network::ListeningAddrs(vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]);
+        let network = Network::inmemory(listening_addr).await.unwrap();
+
+        let content = network
+            .insert_blob(futures::stream::iter(b"same text".to_vec()))
+            .await
+            .unwrap();
+        let timeline = |timestamp| {
+            let network = network.clone();
+            async move {
+                let post = Post::Original(distrox_types::post::OriginalPost {
+                    content,
+                    content_mime: distrox_types::util::Mime(mime::TEXT_PLAIN_UTF_8),
+                    timestamp: distrox_types::util::OffsetDateTime(
+                        time::OffsetDateTime::now_utc() - time::Duration::seconds(timestamp),
+                    ),
+                });
+                let post = network.insert_post(post).await.unwrap();
+                let node = distrox_types::node::Node {
+                    protocol_version: distrox_types::protocol::ProtocolVersion(0),
+                    parents: vec![],
+                    post: Some(post),
+                };
+                network.insert_node(node).await.unwrap()
+            }
+        };
+        let (alice, bob) = (timeline(1).await, timeline(2).await);
+
+        let everything = PinningPolicy {
+            max_age: None,
+            max_bytes: None,
+        };
+        for head in [alice, bob] {
+            apply_to_timeline(&network, head, &everything, &[])
+                .await
+                .unwrap();
+        }
+
+        // Dropping alice keeps what the timeline of bob has as well
+        apply_to_timeline(&network, alice, &PinningPolicy::nothing(), &[bob])
+            .await
+            .unwrap();
+        assert!(!network.is_pinned(alice).await.unwrap());
+        assert!(network.is_pinned(content).await.unwrap());
+
+        apply_to_timeline(&network, bob, &PinningPolicy::nothing(), &[])
+            .await
+            .unwrap();
+        assert!(!network.is_pinned(content).await.unwrap());
+    }
+}
@@ -0,0 +1,77 @@
+//! Separate identities on one installation
+//!
+//! Every profile has its own configuration, state, keys and blockstore. The default profile uses
+//! the XDG directories of the prefix itself, named profiles live in `profiles/<name>` below them.
+
+use crate::error::Error;
+
+/// The name the default profile is listed as
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The XDG directories of `profile`, `None` being the default profile
+pub fn xdg_directories(prefix: &str, profile: Option<&str>) -> Result<xdg::BaseDirectories, Error> {
+    match profile.filter(|profile| *profile != DEFAULT_PROFILE) {
+        None => xdg::BaseDirectories::with_prefix(prefix),
+        Some(profile) => {
+            check_name(profile)?;
+            xdg::BaseDirectories::with_profile(prefix, format!("profiles/{profile}"))
+        }
+    }
+    .map_err(Error::from)
+}
+
+/// The profiles that are set up, the default profile first
+///
+/// A profile is set up once it has a configuration file, the default profile is always listed.
+pub fn list(prefix: &str) -> Result<Vec<String>, Error> {
+    let profiles_dir = xdg::BaseDirectories::with_prefix(prefix)?
+        .get_config_home()
+        .join("profiles");
+
+    let mut names = match std::fs::read_dir(profiles_dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().join("config.toml").is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| check_name(name).is_ok())
+            .collect::<Vec<_>>(),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(Error::ListingProfiles(error)),
+    };
+
+    names.sort();
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    Ok(names)
+}
+
+/// Profile names become directory names, so they must not reach outside of the profiles directory
+fn check_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name != DEFAULT_PROFILE
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidProfileName(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_names() {
+        for name in ["work", "team-2", "personal_account", "a.b"] {
+            assert!(check_name(name).is_ok(), "{name}");
+        }
+
+        for name in ["", "..", ".hidden", "a/b", "default", "with space"] {
+            assert!(check_name(name).is_err(), "{name}");
+        }
+    }
+}
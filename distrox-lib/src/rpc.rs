@@ -0,0 +1,507 @@
+//! JSON-RPC 2.0 interface to a running application
+//!
+//! Clients connect to a Unix domain socket and exchange one JSON object per line.
+//!
+//! # Requests
+//!
+//! Every `Command` is a method, named like the variant in snake case, with the fields of the
+//! variant as named params:
+//!
+//! ```text
+//! --> {"jsonrpc": "2.0", "id": 1, "method": "post_text", "params": {"text": "Hello"}}
+//! <-- {"jsonrpc": "2.0", "id": 1, "result": {"type": "node", "node_id": "bafy..."}}
+//! ```
+//!
+//! The result is `null` if the command has nothing to report, otherwise one of the
+//! `CommandOutput` variants, tagged with `type`. Requests without `id` are notifications and
+//! get no response.
+//!
+//! # Events
+//!
+//! `subscribe_events` makes the server send every `Event` as notification on the connection,
+//! until `unsubscribe_events` is called:
+//!
+//! ```text
+//! <-- {"jsonrpc": "2.0", "method": "event", "params": {"type": "discovered", ...}}
+//! ```
+//!
+//! # Errors
+//!
+//! | code   | meaning                                                |
+//! |--------|--------------------------------------------------------|
+//! | -32700 | The line is not valid JSON                             |
+//! | -32600 | The JSON is not a valid request                        |
+//! | -32601 | There is no such method                                |
+//! | -32602 | The params do not fit the method, or cannot be parsed  |
+//! | -32000 | The command failed for another reason                  |
+//! | -32001 | The command needs the network, but we are offline      |
+//! | -32002 | The command needs an account, or conflicts with it     |
+//! | -32003 | Something the command refers to does not exist         |
+//! | -32004 | The application is shutting down                       |
+//!
+//! `Client` implements the other side, for frontends that talk to a running daemon.
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde_json::json;
+use serde_json::Value;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::unix::OwnedReadHalf;
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+use tracing::{debug, warn};
+
+use crate::command::Command;
+use crate::command::CommandOutput;
+use crate::command::CommandResult;
+use crate::command::CommandSender;
+use crate::error::Error;
+use crate::event::Event;
+use crate::event::EventReceiver;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const COMMAND_FAILED: i64 = -32000;
+pub const OFFLINE: i64 = -32001;
+pub const ACCOUNT: i64 = -32002;
+pub const NOT_FOUND: i64 = -32003;
+pub const SHUTTING_DOWN: i64 = -32004;
+
+/// Serve clients on `listener` until the application stops
+pub async fn serve(listener: UnixListener, commands: CommandSender, events: EventReceiver) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let connection = Connection {
+                    commands: commands.clone(),
+                    events: events.resubscribe(),
+                };
+                tokio::spawn(connection.serve(stream));
+            }
+            Err(error) => warn!(?error, "Failed to accept control connection"),
+        }
+    }
+}
+
+struct Connection {
+    commands: CommandSender,
+    events: EventReceiver,
+}
+
+impl Connection {
+    async fn serve(self, stream: UnixStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let (outgoing, mut outgoing_rx) = tokio::sync::mpsc::channel::<Value>(64);
+
+        let writer_task = tokio::spawn(async move {
+            while let Some(message) = outgoing_rx.recv().await {
+                let mut line = message.to_string();
+                line.push('\n');
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut event_task: Option<tokio::task::JoinHandle<()>> = None;
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(error) => {
+                    debug!(?error, "Control connection failed");
+                    break;
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let request = match parse_request(&line) {
+                Ok(request) => request,
+                Err((id, code, message)) => {
+                    let _ = outgoing.send(error_response(id, code, message)).await;
+                    continue;
+                }
+            };
+
+            let result = match request.method.as_str() {
+                "subscribe_events" => {
+                    if event_task.is_none() {
+                        let events = self.events.resubscribe();
+                        event_task = Some(tokio::spawn(forward_events(events, outgoing.clone())));
+                    }
+                    Ok(Value::Null)
+                }
+                "unsubscribe_events" => {
+                    if let Some(task) = event_task.take() {
+                        task.abort();
+                    }
+                    Ok(Value::Null)
+                }
+                _ => self.call(request.method, request.params).await,
+            };
+
+            let Some(id) = request.id else {
+                continue;
+            };
+
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => error_response(id, code, message),
+            };
+            if outgoing.send(response).await.is_err() {
+                break;
+            }
+        }
+
+        if let Some(task) = event_task {
+            task.abort();
+        }
+        drop(outgoing);
+        let _ = writer_task.await;
+    }
+
+    async fn call(&self, method: String, params: Value) -> Result<Value, (i64, String)> {
+        let command = parse_command(method, params)?;
+        match self.commands.request(command).await {
+            Ok(CommandOutput::Done) => Ok(Value::Null),
+            Ok(output) => {
+                serde_json::to_value(output).map_err(|error| (COMMAND_FAILED, error.to_string()))
+            }
+            Err(error) => Err((error_code(&error), error.to_string())),
+        }
+    }
+}
+
+/// Connection to a daemon serving the JSON-RPC interface
+pub struct Client {
+    path: PathBuf,
+    lines: tokio::io::Lines<tokio::io::BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl Client {
+    pub async fn connect(path: &Path) -> Result<Self, Error> {
+        let stream =
+            UnixStream::connect(path)
+                .await
+                .map_err(|source| Error::ControlConnection {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+        let (reader, writer) = stream.into_split();
+
+        Ok(Client {
+            path: path.to_path_buf(),
+            lines: tokio::io::BufReader::new(reader).lines(),
+            writer,
+            next_id: 0,
+        })
+    }
+
+    /// Let the daemon handle a command and wait for its result
+    ///
+    /// Errors of the daemon are returned as `Error::Rpc`.
+    pub async fn request(&mut self, command: Command) -> CommandResult {
+        self.next_id += 1;
+        let id = self.next_id;
+
+        let mut request = serde_json::to_value(command).map_err(Error::EncodingRpcRequest)?;
+        if let Value::Object(request) = &mut request {
+            request.insert("jsonrpc".to_string(), json!("2.0"));
+            request.insert("id".to_string(), json!(id));
+        }
+        let mut line = request.to_string();
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|source| self.connection_error(source))?;
+
+        loop {
+            let line = self
+                .lines
+                .next_line()
+                .await
+                .map_err(|source| self.connection_error(source))?
+                .ok_or(Error::BackendGone)?;
+            let mut response: Value =
+                serde_json::from_str(&line).map_err(Error::DecodingRpcResponse)?;
+
+            // Event notifications have no id
+            if response.get("id") != Some(&json!(id)) {
+                continue;
+            }
+
+            if let Some(error) = response.get("error") {
+                return Err(Error::Rpc {
+                    code: error
+                        .get("code")
+                        .and_then(Value::as_i64)
+                        .unwrap_or(COMMAND_FAILED),
+                    message: error
+                        .get("message")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                });
+            }
+
+            return match response.get_mut("result").map(Value::take) {
+                Some(Value::Null) => Ok(CommandOutput::Done),
+                Some(result) => serde_json::from_value(result).map_err(Error::DecodingRpcResponse),
+                None => Err(Error::InvalidRpcResponse("neither result nor error")),
+            };
+        }
+    }
+
+    fn connection_error(&self, source: std::io::Error) -> Error {
+        Error::ControlConnection {
+            path: self.path.clone(),
+            source,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    params: Value,
+}
+
+fn parse_request(line: &str) -> Result<Request, (Value, i64, String)> {
+    let value: Value = serde_json::from_str(line)
+        .map_err(|error| (Value::Null, PARSE_ERROR, error.to_string()))?;
+
+    let id = value.get("id").cloned();
+    let invalid = |message: &str| {
+        (
+            id.clone().unwrap_or(Value::Null),
+            INVALID_REQUEST,
+            message.to_string(),
+        )
+    };
+
+    if value.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err(invalid("jsonrpc must be \"2.0\""));
+    }
+
+    let method = value
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("method must be a string"))?
+        .to_string();
+
+    let params = value.get("params").cloned().unwrap_or(Value::Null);
+    if !(params.is_null() || params.is_object()) {
+        return Err(invalid("params must be an object"));
+    }
+
+    Ok(Request { id, method, params })
+}
+
+fn parse_command(method: String, params: Value) -> Result<Command, (i64, String)> {
+    if !Command::METHODS.contains(&method.as_str()) {
+        return Err((METHOD_NOT_FOUND, format!("Unknown method {method}")));
+    }
+
+    let mut command = serde_json::Map::new();
+    command.insert("method".to_string(), Value::String(method.clone()));
+    // Commands without fields have no params at all
+    if params.as_object().map(|p| !p.is_empty()).unwrap_or(false) {
+        command.insert("params".to_string(), params);
+    }
+
+    serde_json::from_value(Value::Object(command))
+        .map_err(|error| (INVALID_PARAMS, error.to_string()))
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn error_code(error: &Error) -> i64 {
+    match error {
+        Error::ParseMultiAddr { .. } | Error::Cid(_) | Error::ParsePeerId(_) => INVALID_PARAMS,
+        Error::Offline => OFFLINE,
+        Error::AccountExists
+        | Error::NoAccount
+        | Error::NoAccountKey
+        | Error::NotAccountKey
+        | Error::InvalidDelegation
+        | Error::DeviceNotDelegated
+        | Error::UnknownAccount
+        | Error::FollowingOwnAccount => ACCOUNT,
+        Error::NoTimeline | Error::NodeWithoutPost { .. } | Error::UnknownCid => NOT_FOUND,
+        Error::BackendGone => SHUTTING_DOWN,
+        Error::Rpc { code, .. } => *code,
+        _ => COMMAND_FAILED,
+    }
+}
+
+async fn forward_events(mut events: EventReceiver, outgoing: tokio::sync::mpsc::Sender<Value>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                debug!(missed, "Control connection missed events");
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        };
+
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "event",
+            "params": event_to_json(&event),
+        });
+        if outgoing.send(notification).await.is_err() {
+            return;
+        }
+    }
+}
+
+pub fn event_to_json(event: &Event) -> Value {
+    match event {
+        Event::ConnectionEstablished { address } => json!({
+            "type": "connection_established",
+            "address": address.to_string(),
+        }),
+        Event::ConnectionClosed { address } => json!({
+            "type": "connection_closed",
+            "address": address.to_string(),
+        }),
+        Event::PubSubSubscribe(peer_id) => json!({
+            "type": "pubsub_subscribe",
+            "peer_id": peer_id.to_string(),
+        }),
+        Event::PubSubUnsubscribe(peer_id) => json!({
+            "type": "pubsub_unsubscribe",
+            "peer_id": peer_id.to_string(),
+        }),
+        Event::Discovered(discovery) => json!({
+            "type": "discovered",
+            "author": discovery.author.to_string(),
+            "announced_by": discovery.announced_by.to_string(),
+            "node_id": discovery.node_id.to_string(),
+            "post_id": discovery.post_id.to_string(),
+        }),
+        Event::Metrics(metrics) => json!({
+            "type": "metrics",
+            "connections": metrics
+                .connections
+                .iter()
+                .map(|(peer_id, count)| (peer_id.to_string(), json!(count)))
+                .collect::<serde_json::Map<_, _>>(),
+            "bytes_in": metrics.bytes_in,
+            "bytes_out": metrics.bytes_out,
+            "traffic": metrics
+                .traffic
+                .iter()
+                .map(|(peer_id, traffic)| {
+                    let traffic = json!({
+                        "bytes_in": traffic.bytes_in,
+                        "bytes_out": traffic.bytes_out,
+                    });
+                    (peer_id.to_string(), traffic)
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            "blocks_fetched": metrics.blocks_fetched,
+            "blocks_served": metrics.blocks_served,
+            "gossip_messages_received": metrics.gossip_messages_received,
+            "gossip_messages_published": metrics.gossip_messages_published,
+        }),
+        Event::TimelineChanged { head } => json!({
+            "type": "timeline_changed",
+            "head": head.to_string(),
+        }),
+        Event::ConfigReloaded(changes) => json!({
+            "type": "config_reloaded",
+            "applied": changes.applied,
+            "restart_required": changes.restart_required,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let command = parse_command("post_text".to_string(), json!({ "text": "Hello" })).unwrap();
+        assert!(matches!(command, Command::PostText { text } if text == "Hello"));
+
+        let command = parse_command("create_account".to_string(), Value::Null).unwrap();
+        assert!(matches!(command, Command::CreateAccount));
+
+        let (code, _) = parse_command("no_such_method".to_string(), Value::Null).unwrap_err();
+        assert_eq!(code, METHOD_NOT_FOUND);
+
+        let (code, _) = parse_command("post_text".to_string(), json!({ "txt": "" })).unwrap_err();
+        assert_eq!(code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_methods_are_commands() {
+        for method in Command::METHODS {
+            let mut command = serde_json::Map::new();
+            command.insert("method".to_string(), json!(method));
+            let error = serde_json::from_value::<Command>(Value::Object(command)).err();
+            assert!(
+                !error.map_or(false, |e| e.to_string().starts_with("unknown variant")),
+                "{method} is not a command"
+            );
+        }
+    }
+
+    #[test]
+    fn test_commands_are_methods() {
+        // serde lists all variants it knows when it is given an unknown one
+        let error = serde_json::from_value::<Command>(json!({ "method": "" })).unwrap_err();
+        let error = error.to_string();
+        let (_, expected) = error
+            .split_once("expected one of ")
+            .expect("unknown variant error");
+        let variants = expected
+            .split(", ")
+            .map(|variant| variant.trim_matches('`'))
+            .collect::<Vec<_>>();
+
+        for variant in variants.iter() {
+            assert!(
+                Command::METHODS.contains(variant),
+                "{variant} is missing in Command::METHODS"
+            );
+        }
+        assert_eq!(variants.len(), Command::METHODS.len());
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let request =
+            parse_request(r#"{"jsonrpc": "2.0", "id": 7, "method": "collect_garbage"}"#).unwrap();
+        assert_eq!(request.id, Some(json!(7)));
+        assert_eq!(request.method, "collect_garbage");
+
+        let (_, code, _) = parse_request("not json").unwrap_err();
+        assert_eq!(code, PARSE_ERROR);
+
+        let (id, code, _) = parse_request(r#"{"id": 1, "method": "quit_app"}"#).unwrap_err();
+        assert_eq!((id, code), (json!(1), INVALID_REQUEST));
+    }
+}
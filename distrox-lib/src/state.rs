@@ -1,43 +1,217 @@
 use std::path::PathBuf;
 
-use tokio::io::AsyncWriteExt;
+use serde::Deserialize;
 
+use crate::cid_string::CidString;
 use crate::error::Error;
+use crate::file::ReadError;
+
+/// Version of the layout of the state file, stored as `version` in the file
+///
+/// Files written before the version was introduced have none and count as version 0. Fields were
+/// only ever added with defaults up to then, so every unversioned layout loads the same way.
+const STATE_VERSION: u32 = 2;
+
+/// How many addresses are remembered per peer, the ones learned most recently are kept
+const MAX_PEER_ADDRS: usize = 8;
+
+/// How many peers the peer book holds, the ones seen most recently are kept
+const MAX_KNOWN_PEERS: usize = 256;
+
+/// How long a peer that was not seen anymore stays in the peer book
+const PEER_EXPIRY: time::Duration = time::Duration::days(30);
+
+/// How precisely `last_seen` is kept, more often is not worth rewriting the state file for
+const LAST_SEEN_RESOLUTION: time::Duration = time::Duration::hours(1);
+
+type Migration = fn(&mut toml::Table) -> Result<(), Error>;
+
+/// Upgrades of the state file, the migration at index `n` turns version `n` into version `n + 1`
+const MIGRATIONS: [Migration; STATE_VERSION as usize] = [migrate_unversioned, migrate_cid_bytes];
+
+fn migrate_unversioned(_: &mut toml::Table) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Version 2 stores CIDs as strings instead of arrays of their bytes
+fn migrate_cid_bytes(table: &mut toml::Table) -> Result<(), Error> {
+    fn convert(value: &mut toml::Value) -> Result<(), Error> {
+        let bytes = Vec::<u8>::deserialize(value.clone()).map_err(Error::ParsingState)?;
+        let cid = cid::Cid::read_bytes(bytes.as_slice())?;
+        *value = toml::Value::String(cid.to_string());
+        Ok(())
+    }
+
+    for key in ["latest_post", "account"] {
+        if let Some(value) = table.get_mut(key) {
+            convert(value)?;
+        }
+    }
+
+    if let Some(toml::Value::Array(heads)) = table.get_mut("pending_heads") {
+        heads.iter_mut().try_for_each(convert)?;
+    }
+
+    if let Some(toml::Value::Array(follows)) = table.get_mut("follows") {
+        for follow in follows.iter_mut().filter_map(toml::Value::as_table_mut) {
+            for key in ["account", "head"] {
+                if let Some(value) = follow.get_mut(key) {
+                    convert(value)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse a state file of any known version, upgrading it to the current layout
+fn parse_state(text: &str) -> Result<StateInner, Error> {
+    let mut table = toml::from_str::<toml::Table>(text).map_err(Error::ParsingState)?;
+
+    let version = match table.remove("version") {
+        Some(version) => u32::deserialize(version).map_err(Error::ParsingState)?,
+        None => 0,
+    };
+
+    // A newer version would lose whatever we do not understand when we save it again
+    if version > STATE_VERSION {
+        return Err(Error::UnsupportedStateVersion(version));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut table)?;
+    }
+
+    StateInner::deserialize(toml::Value::Table(table)).map_err(Error::ParsingState)
+}
+
+/// The state as it is written to disk
+#[derive(serde::Serialize)]
+struct VersionedState<'a> {
+    version: u32,
+
+    #[serde(flatten)]
+    state: &'a StateInner,
+}
 
 pub struct State {
     path: PathBuf,
     state_inner: StateInner,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
 struct StateInner {
-    latest_post: Option<Vec<u8>>,
+    latest_post: Option<CidString>,
+
+    #[serde(default)]
+    account: Option<CidString>,
+
+    /// Heads of other devices of the account, which are not yet merged into our timeline
+    #[serde(default)]
+    pending_heads: Vec<CidString>,
+
+    #[serde(default)]
+    follows: Vec<Follow>,
+
+    /// Messages that could not be published yet, because no peer was listening
+    #[serde(default)]
+    outbox: Vec<OutboxEntry>,
+
+    /// Peers we were successfully connected to
+    #[serde(default)]
+    peer_book: Vec<KnownPeer>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct KnownPeer {
+    peer_id: String,
+    addrs: Vec<String>,
+
+    #[serde(with = "time::serde::rfc3339")]
+    last_seen: time::OffsetDateTime,
+}
+
+impl KnownPeer {
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    pub fn addrs(&self) -> &[String] {
+        &self.addrs
+    }
+
+    pub fn last_seen(&self) -> time::OffsetDateTime {
+        self.last_seen
+    }
+}
+
+/// A message waiting to be published on a pubsub topic
+///
+/// Per topic, a newer entry replaces an older one in the same slot, so only the latest head of a
+/// timeline is published once connectivity returns.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct OutboxEntry {
+    pub topic: String,
+    pub slot: String,
+    pub data: Vec<u8>,
+}
+
+/// An author we follow, with what we know about their timeline
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Follow {
+    author: String,
+    account: Option<CidString>,
+    head: Option<CidString>,
+}
+
+impl Follow {
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn account(&self) -> Option<cid::Cid> {
+        self.account.map(|account| account.0)
+    }
+
+    pub fn head(&self) -> Option<cid::Cid> {
+        self.head.map(|head| head.0)
+    }
 }
 
 impl State {
+    /// The state of a fresh installation, nothing is written until `save` is called
+    pub fn empty(path: PathBuf) -> Self {
+        State {
+            path,
+            state_inner: StateInner::default(),
+        }
+    }
+
     pub async fn load_from_path(path: PathBuf) -> Result<Self, Error> {
-        tokio::fs::read_to_string(&path)
+        let parse = |text: &str| {
+            parse_state(text).map_err(|error| match error {
+                Error::UnsupportedStateVersion(_) => ReadError::Unsupported(error),
+                error => ReadError::Parse(error),
+            })
+        };
+
+        crate::file::read_with_backup(&path, parse)
             .await
-            .map_err(Error::ReadingState)
-            .and_then(|text| toml::from_str(&text).map_err(Error::ParsingState))
+            .map_err(|error| match error {
+                ReadError::Io(error) => Error::ReadingState(error),
+                ReadError::Parse(error) | ReadError::Unsupported(error) => error,
+            })
             .map(|state_inner| State { path, state_inner })
     }
 
     pub async fn save(&self) -> Result<(), Error> {
-        let ser = toml::to_string(&self.state_inner).map_err(Error::SerializingState)?;
+        let ser = toml::to_string(&VersionedState {
+            version: STATE_VERSION,
+            state: &self.state_inner,
+        })
+        .map_err(Error::SerializingState)?;
 
-        tokio::fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .append(false)
-            .create(true)
-            .open(&self.path)
-            .await
-            .map_err(|source| Error::OpenStateFile {
-                path: self.path.to_path_buf(),
-                source,
-            })?
-            .write_all(ser.as_bytes())
+        crate::file::write_atomically(&self.path, ser.as_bytes())
             .await
             .map_err(|source| Error::WritingState {
                 path: self.path.to_path_buf(),
@@ -45,12 +219,414 @@ impl State {
             })
     }
 
-    pub fn latest_post(&self) -> Option<&[u8]> {
-        self.state_inner.latest_post.as_deref()
+    pub fn latest_post(&self) -> Option<cid::Cid> {
+        self.state_inner.latest_post.map(|post| post.0)
+    }
+
+    /// Store the new head of the timeline
+    ///
+    /// The new head is expected to merge all pending heads, so these are cleared.
+    pub async fn store_latest_post(&mut self, post: cid::Cid) -> Result<(), Error> {
+        self.state_inner.latest_post = Some(CidString(post));
+        self.state_inner.pending_heads.clear();
+        self.save().await
+    }
+
+    pub fn account(&self) -> Option<cid::Cid> {
+        self.state_inner.account.map(|account| account.0)
+    }
+
+    pub async fn store_account(&mut self, account: cid::Cid) -> Result<(), Error> {
+        self.state_inner.account = Some(CidString(account));
+        self.save().await
+    }
+
+    pub fn pending_heads(&self) -> impl Iterator<Item = cid::Cid> + '_ {
+        self.state_inner.pending_heads.iter().map(|head| head.0)
+    }
+
+    pub async fn add_pending_head(&mut self, head: cid::Cid) -> Result<(), Error> {
+        let head = CidString(head);
+        if !self.state_inner.pending_heads.contains(&head) {
+            self.state_inner.pending_heads.push(head);
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    pub fn follows(&self) -> &[Follow] {
+        &self.state_inner.follows
+    }
+
+    pub fn follow(&self, author: &str) -> Option<&Follow> {
+        self.state_inner.follows.iter().find(|f| f.author == author)
+    }
+
+    pub async fn add_follow(&mut self, author: String) -> Result<(), Error> {
+        if self.follow(&author).is_none() {
+            self.state_inner.follows.push(Follow {
+                author,
+                account: None,
+                head: None,
+            });
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn remove_follow(&mut self, author: &str) -> Result<Option<Follow>, Error> {
+        let Some(position) = self.state_inner.follows.iter().position(|f| f.author == author) else {
+            return Ok(None);
+        };
+
+        let follow = self.state_inner.follows.remove(position);
+        self.save().await?;
+        Ok(Some(follow))
+    }
+
+    pub async fn store_followed_account(
+        &mut self,
+        author: &str,
+        account: cid::Cid,
+    ) -> Result<(), Error> {
+        if let Some(follow) = self.follow_mut(author) {
+            follow.account = Some(CidString(account));
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn store_followed_head(&mut self, author: &str, head: cid::Cid) -> Result<(), Error> {
+        if let Some(follow) = self.follow_mut(author) {
+            follow.head = Some(CidString(head));
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    fn follow_mut(&mut self, author: &str) -> Option<&mut Follow> {
+        self.state_inner
+            .follows
+            .iter_mut()
+            .find(|f| f.author == author)
+    }
+
+    pub fn outbox(&self) -> &[OutboxEntry] {
+        &self.state_inner.outbox
+    }
+
+    pub async fn queue_outbox_entry(&mut self, entry: OutboxEntry) -> Result<(), Error> {
+        self.state_inner
+            .outbox
+            .retain(|e| e.topic != entry.topic || e.slot != entry.slot);
+        self.state_inner.outbox.push(entry);
+        self.save().await
     }
 
-    pub async fn store_latest_post(&mut self, post: Vec<u8>) -> Result<(), Error> {
-        self.state_inner.latest_post = Some(post);
+    pub async fn remove_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), Error> {
+        self.state_inner.outbox.retain(|e| e != entry);
         self.save().await
     }
+
+    pub fn peer_book(&self) -> &[KnownPeer] {
+        &self.state_inner.peer_book
+    }
+
+    /// Remember that we were connected to these peers at `last_seen`
+    ///
+    /// Peers not seen for `PEER_EXPIRY` are forgotten. The state file is only written if the
+    /// peer book changed.
+    pub async fn record_peers(
+        &mut self,
+        peers: impl IntoIterator<Item = (String, Vec<String>)>,
+        last_seen: time::OffsetDateTime,
+    ) -> Result<(), Error> {
+        let peer_book = &mut self.state_inner.peer_book;
+        let mut changed = false;
+
+        for (peer_id, addrs) in peers {
+            let known = match peer_book.iter_mut().position(|p| p.peer_id == peer_id) {
+                Some(position) => &mut peer_book[position],
+                None => {
+                    changed = true;
+                    peer_book.push(KnownPeer {
+                        peer_id,
+                        addrs: Vec::new(),
+                        last_seen,
+                    });
+                    peer_book.last_mut().unwrap()
+                }
+            };
+
+            if last_seen - known.last_seen >= LAST_SEEN_RESOLUTION {
+                known.last_seen = last_seen;
+                changed = true;
+            }
+            for addr in addrs {
+                if !known.addrs.contains(&addr) {
+                    known.addrs.push(addr);
+                    changed = true;
+                }
+            }
+            let excess = known.addrs.len().saturating_sub(MAX_PEER_ADDRS);
+            known.addrs.drain(..excess);
+        }
+
+        let known_peers = peer_book.len();
+        peer_book.retain(|p| last_seen - p.last_seen < PEER_EXPIRY);
+        peer_book.sort_by_key(|p| std::cmp::Reverse(p.last_seen));
+        peer_book.truncate(MAX_KNOWN_PEERS);
+        changed |= peer_book.len() != known_peers;
+
+        if changed {
+            self.save().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATEST_POST: [u8; 36] = [
+        1, 113, 18, 32, 94, 30, 43, 202, 195, 5, 149, 139, 39, 7, 124, 161, 54, 243, 95, 10, 186,
+        231, 207, 56, 201, 175, 103, 143, 125, 34, 14, 208, 203, 81, 212, 248,
+    ];
+
+    /// One state file per layout that was ever written, oldest first
+    const FIXTURES: [&str; 7] = [
+        include_str!("../fixtures/state/v0-baseline.toml"),
+        include_str!("../fixtures/state/v0-account.toml"),
+        include_str!("../fixtures/state/v0-follows.toml"),
+        include_str!("../fixtures/state/v0-outbox.toml"),
+        include_str!("../fixtures/state/v0-peer-book.toml"),
+        include_str!("../fixtures/state/v1.toml"),
+        include_str!("../fixtures/state/v2.toml"),
+    ];
+
+    #[test]
+    fn test_load_every_version() {
+        for (n, fixture) in FIXTURES.iter().enumerate() {
+            let state = parse_state(fixture).unwrap();
+            let latest_post = state.latest_post.map(|post| post.0.to_bytes());
+            assert_eq!(latest_post.as_deref(), Some(&LATEST_POST[..]));
+            assert_eq!(state.account.is_some(), n >= 1);
+            assert_eq!(state.pending_heads.len(), usize::from(n >= 1));
+            assert_eq!(state.follows.len(), usize::from(n >= 2));
+            assert_eq!(state.outbox.len(), usize::from(n >= 3));
+            assert_eq!(state.peer_book.len(), usize::from(n >= 4));
+        }
+
+        assert!(parse_state("").unwrap().latest_post.is_none());
+    }
+
+    #[test]
+    fn test_save_writes_current_version() {
+        let state = parse_state(FIXTURES[FIXTURES.len() - 1]).unwrap();
+        let text = toml::to_string(&VersionedState {
+            version: STATE_VERSION,
+            state: &state,
+        })
+        .unwrap();
+
+        assert!(text.starts_with(&format!("version = {STATE_VERSION}\n")));
+        let reloaded = parse_state(&text).unwrap();
+        assert_eq!(reloaded.latest_post, state.latest_post);
+        assert_eq!(reloaded.follows.len(), 1);
+        assert_eq!(reloaded.peer_book.len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_cid_bytes() {
+        let migrated = parse_state(FIXTURES[5]).unwrap();
+        let text = toml::to_string(&VersionedState {
+            version: STATE_VERSION,
+            state: &migrated,
+        })
+        .unwrap();
+
+        assert_eq!(text, FIXTURES[6]);
+    }
+
+    fn entry(topic: &str, slot: &str, data: &[u8]) -> OutboxEntry {
+        OutboxEntry {
+            topic: topic.to_string(),
+            slot: slot.to_string(),
+            data: data.to_vec(),
+        }
+    }
+
+    async fn temp_state(name: &str) -> (PathBuf, State) {
+        let dir = std::env::temp_dir().join(format!("distrox-{name}-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let state = State::empty(dir.join("state.toml"));
+        (dir, state)
+    }
+
+    #[tokio::test]
+    async fn test_outbox_enqueue() {
+        let (dir, mut state) = temp_state("outbox-enqueue").await;
+
+        state
+            .queue_outbox_entry(entry("a", "head", b"1"))
+            .await
+            .unwrap();
+        state
+            .queue_outbox_entry(entry("b", "head", b"2"))
+            .await
+            .unwrap();
+        state
+            .queue_outbox_entry(entry("a", "account", b"3"))
+            .await
+            .unwrap();
+        assert_eq!(state.outbox().len(), 3);
+
+        let reloaded = State::load_from_path(state.path.clone()).await.unwrap();
+        assert_eq!(reloaded.outbox(), state.outbox());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_outbox_replace_by_slot() {
+        let (dir, mut state) = temp_state("outbox-replace").await;
+
+        state
+            .queue_outbox_entry(entry("a", "head", b"1"))
+            .await
+            .unwrap();
+        state
+            .queue_outbox_entry(entry("b", "head", b"2"))
+            .await
+            .unwrap();
+        state
+            .queue_outbox_entry(entry("a", "head", b"3"))
+            .await
+            .unwrap();
+        assert_eq!(
+            state.outbox(),
+            [entry("b", "head", b"2"), entry("a", "head", b"3")]
+        );
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_outbox_flush() {
+        let (dir, mut state) = temp_state("outbox-flush").await;
+
+        state
+            .queue_outbox_entry(entry("a", "head", b"1"))
+            .await
+            .unwrap();
+        state
+            .queue_outbox_entry(entry("b", "head", b"2"))
+            .await
+            .unwrap();
+
+        // What flushing does with each published entry
+        while let Some(published) = state.outbox().first().cloned() {
+            state.remove_outbox_entry(&published).await.unwrap();
+        }
+        assert!(state.outbox().is_empty());
+
+        let reloaded = State::load_from_path(state.path.clone()).await.unwrap();
+        assert!(reloaded.outbox().is_empty());
+
+        // An entry replaced while the old one was being published stays queued
+        state
+            .queue_outbox_entry(entry("a", "head", b"3"))
+            .await
+            .unwrap();
+        state
+            .remove_outbox_entry(&entry("a", "head", b"1"))
+            .await
+            .unwrap();
+        assert_eq!(state.outbox(), [entry("a", "head", b"3")]);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record_peers_only_saves_changes() {
+        let (dir, mut state) = temp_state("peer-book-changes").await;
+        let now = time::OffsetDateTime::now_utc();
+        let peer = |addr: &str| ("peer".to_string(), vec![addr.to_string()]);
+
+        state
+            .record_peers([peer("/ip4/1.2.3.4/tcp/1")], now)
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&state.path).await.unwrap();
+
+        // Seen again shortly after, at a known address
+        let soon = now + time::Duration::minutes(1);
+        state
+            .record_peers([peer("/ip4/1.2.3.4/tcp/1")], soon)
+            .await
+            .unwrap();
+        assert!(!tokio::fs::try_exists(&state.path).await.unwrap());
+        assert_eq!(state.peer_book()[0].last_seen(), now);
+
+        state
+            .record_peers([peer("/ip4/1.2.3.4/tcp/2")], soon)
+            .await
+            .unwrap();
+        assert!(tokio::fs::try_exists(&state.path).await.unwrap());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record_peers_is_bounded() {
+        let (dir, mut state) = temp_state("peer-book-bounded").await;
+        let now = time::OffsetDateTime::now_utc();
+
+        let addrs = (0..MAX_PEER_ADDRS + 2)
+            .map(|port| format!("/ip4/1.2.3.4/tcp/{port}"))
+            .collect::<Vec<_>>();
+        let old = now - PEER_EXPIRY;
+        state
+            .record_peers([("old".to_string(), addrs.clone())], old)
+            .await
+            .unwrap();
+        assert_eq!(state.peer_book()[0].addrs(), &addrs[2..]);
+
+        let peers = (0..MAX_KNOWN_PEERS + 1).map(|n| (format!("peer{n}"), Vec::new()));
+        state.record_peers(peers, now).await.unwrap();
+        assert_eq!(state.peer_book().len(), MAX_KNOWN_PEERS);
+        assert!(state.peer_book().iter().all(|p| p.peer_id() != "old"));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_reject_newer_version() {
+        let text = format!("version = {}\n", STATE_VERSION + 1);
+        assert!(matches!(
+            parse_state(&text),
+            Err(Error::UnsupportedStateVersion(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_newer_version_ignores_backup() {
+        let (dir, state) = temp_state("newer-version").await;
+        state.save().await.unwrap();
+        state.save().await.unwrap();
+        let path = dir.join("state.toml");
+        assert!(tokio::fs::try_exists(crate::file::backup_path(&path))
+            .await
+            .unwrap());
+
+        let text = format!("version = {}\n", STATE_VERSION + 1);
+        tokio::fs::write(&path, text).await.unwrap();
+        assert!(matches!(
+            State::load_from_path(path).await,
+            Err(Error::UnsupportedStateVersion(_))
+        ));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
 }
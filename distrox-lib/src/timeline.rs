@@ -0,0 +1,646 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use distrox_types::post::Post;
+
+use crate::cid_string::CidString;
+use crate::error::Error;
+use crate::network::Network;
+
+/// How many nodes are visited at most when looking for a node in the history of a head
+const ANCESTRY_LIMIT: usize = 1024;
+
+/// A post in a timeline, as shown to the user
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TimelineEntry {
+    /// The account (or device, without account) whose timeline contains the post
+    pub author: String,
+    pub node_id: String,
+    pub post_id: String,
+    pub post: PostView,
+}
+
+impl TimelineEntry {
+    fn timestamp(&self) -> Option<time::OffsetDateTime> {
+        match &self.post {
+            PostView::Original { timestamp, .. } => Some(*timestamp),
+            PostView::Repost { .. } | PostView::Announce { .. } => None,
+        }
+    }
+}
+
+/// The post of a `TimelineEntry`, mirroring `distrox_types::post::Post`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PostView {
+    Original {
+        content_id: String,
+        content_mime: String,
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: time::OffsetDateTime,
+
+        /// The content, if it is text and available locally
+        text: Option<String>,
+    },
+
+    Repost {
+        node_id: String,
+        post_id: String,
+
+        /// Whose timeline the reposted node is in, if it is one we know
+        #[serde(default)]
+        author: Option<String>,
+    },
+
+    Announce {
+        node_id: String,
+        post_id: String,
+
+        /// Whose timeline the announced node is in, if it is one we know
+        #[serde(default)]
+        author: Option<String>,
+    },
+}
+
+/// Where a feed continues: for each author, how far their timeline was walked
+///
+/// Authors whose timeline was walked to its end are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TimelineCursor(BTreeMap<String, Position>);
+
+impl TimelineCursor {
+    /// Start the timeline of each author at its head
+    pub fn from_heads(heads: &[(String, cid::Cid)]) -> Self {
+        TimelineCursor(
+            heads
+                .iter()
+                .map(|(author, head)| {
+                    let position = Position {
+                        next: vec![CidString(*head)],
+                        visited: Vec::new(),
+                    };
+                    (author.clone(), position)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// How far the timeline of one author was walked
+///
+/// Nodes can be reached on several paths once devices merged their histories, so the visited
+/// nodes are kept as well, otherwise a later page could show them again.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct Position {
+    /// The nodes to visit next
+    next: Vec<CidString>,
+
+    /// The nodes that were visited on earlier pages
+    #[serde(default)]
+    visited: Vec<CidString>,
+}
+
+/// The posts of one timeline, and where the timeline continues after each of them
+struct Walked {
+    entries: Vec<TimelineEntry>,
+
+    /// The nodes visited so far, in the order of visiting
+    visited: Vec<cid::Cid>,
+
+    /// For each entry, the nodes to visit next and how many nodes were visited up to it
+    after: Vec<(Vec<CidString>, usize)>,
+}
+
+impl Walked {
+    /// Where the timeline continues after its entry `n`
+    fn position(&self, n: usize) -> Position {
+        let (next, visited) = &self.after[n];
+        Position {
+            next: next.clone(),
+            visited: self.visited[..*visited]
+                .iter()
+                .cloned()
+                .map(CidString)
+                .collect(),
+        }
+    }
+}
+
+/// Collect up to `limit` posts of the feed at `cursor`, and where the feed continues after them
+///
+/// Reposts and announcements get the author of the node they refer to, if that node is in the
+/// history of one of `heads`.
+pub async fn page(
+    network: &Network,
+    heads: &[(String, cid::Cid)],
+    cursor: &TimelineCursor,
+    limit: usize,
+) -> Result<(Vec<TimelineEntry>, Option<TimelineCursor>), Error> {
+    // Timelines of authors that were unfollowed since the cursor was made are left out
+    let mut timelines = Vec::with_capacity(cursor.0.len());
+    for (author, position) in cursor.0.iter() {
+        if !heads.iter().any(|(known, _)| known == author) {
+            continue;
+        }
+        timelines.push((author, walk(network, author, position, limit).await?));
+    }
+
+    let (mut entries, taken) = merge_counted(
+        timelines
+            .iter()
+            .map(|(_, walked)| walked.entries.clone())
+            .collect(),
+        limit,
+    );
+
+    let mut next = BTreeMap::new();
+    for ((author, walked), taken) in timelines.into_iter().zip(taken) {
+        let position = match taken.checked_sub(1) {
+            Some(last) => walked.position(last),
+            None => cursor.0[author].clone(),
+        };
+        if !position.next.is_empty() {
+            next.insert(author.clone(), position);
+        }
+    }
+
+    for entry in entries.iter_mut() {
+        resolve_author(network, heads, entry).await?;
+    }
+
+    let next = (!next.is_empty()).then_some(TimelineCursor(next));
+    Ok((entries, next))
+}
+
+/// Find the author of the node a repost or announcement refers to, among the timelines of `heads`
+async fn resolve_author(
+    network: &Network,
+    heads: &[(String, cid::Cid)],
+    entry: &mut TimelineEntry,
+) -> Result<(), Error> {
+    let (node_id, resolved) = match &mut entry.post {
+        PostView::Original { .. } => return Ok(()),
+        PostView::Repost {
+            node_id, author, ..
+        }
+        | PostView::Announce {
+            node_id, author, ..
+        } => (node_id.as_str(), author),
+    };
+    let node_id = cid::Cid::try_from(node_id)?;
+
+    for (author, head) in heads {
+        if contains(network, *head, node_id, false).await? {
+            *resolved = Some(author.clone());
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Collect up to `limit` posts of the timeline continuing at `position`, newest first
+///
+/// Only blocks in the local blockstore are visited, so this never waits for the network.
+async fn walk(
+    network: &Network,
+    author: &str,
+    position: &Position,
+    limit: usize,
+) -> Result<Walked, Error> {
+    let mut entries = Vec::new();
+    let mut after = Vec::new();
+    let mut visited_order = position.visited.iter().map(|cid| cid.0).collect::<Vec<_>>();
+    let mut visited = visited_order.iter().cloned().collect::<HashSet<_>>();
+    let mut queue = position
+        .next
+        .iter()
+        .map(|cid| cid.0)
+        .collect::<VecDeque<_>>();
+
+    while let Some(node_id) = queue.pop_front() {
+        if entries.len() >= limit {
+            break;
+        }
+        if !visited.insert(node_id) {
+            continue;
+        }
+        visited_order.push(node_id);
+
+        let Some(node) = network.get_local_node(node_id).await? else {
+            continue;
+        };
+        queue.extend(node.parents.iter().cloned());
+
+        let Some(post_id) = node.post else {
+            continue;
+        };
+        let Some(post) = network.get_local_post(post_id).await? else {
+            continue;
+        };
+
+        let post = match post {
+            Post::Original(original) => {
+                let text = if original.content_mime.0.type_() == mime::TEXT {
+                    network
+                        .get_local_blob(original.content)
+                        .await?
+                        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                } else {
+                    None
+                };
+
+                PostView::Original {
+                    content_id: original.content.to_string(),
+                    content_mime: original.content_mime.0.to_string(),
+                    timestamp: original.timestamp.0,
+                    text,
+                }
+            }
+            Post::Repost(repost) => PostView::Repost {
+                node_id: repost.node_id.to_string(),
+                post_id: repost.post_id.to_string(),
+                author: None,
+            },
+            Post::Announce(announce) => PostView::Announce {
+                node_id: announce.node_id.to_string(),
+                post_id: announce.post_id.to_string(),
+                author: None,
+            },
+        };
+
+        entries.push(TimelineEntry {
+            author: author.to_string(),
+            node_id: node_id.to_string(),
+            post_id: post_id.to_string(),
+            post,
+        });
+        after.push((frontier(&queue, &visited), visited_order.len()));
+    }
+
+    Ok(Walked {
+        entries,
+        visited: visited_order,
+        after,
+    })
+}
+
+/// The nodes in `queue` that are left to visit, each once
+fn frontier(queue: &VecDeque<cid::Cid>, visited: &HashSet<cid::Cid>) -> Vec<CidString> {
+    let mut seen = HashSet::new();
+    queue
+        .iter()
+        .filter(|cid| !visited.contains(cid) && seen.insert(**cid))
+        .cloned()
+        .map(CidString)
+        .collect()
+}
+
+/// Whether `node_id` is `head` or one of its ancestors
+///
+/// With `fetch`, nodes missing locally are fetched from the network, otherwise only the local
+/// blockstore is looked at. A history longer than `ANCESTRY_LIMIT` nodes is not searched to its
+/// end, in which case `node_id` counts as not found.
+pub async fn contains(
+    network: &Network,
+    head: cid::Cid,
+    node_id: cid::Cid,
+    fetch: bool,
+) -> Result<bool, Error> {
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::from([head]);
+
+    while let Some(current) = queue.pop_front() {
+        if current == node_id {
+            return Ok(true);
+        }
+        if visited.len() >= ANCESTRY_LIMIT {
+            break;
+        }
+        if !visited.insert(current) {
+            continue;
+        }
+
+        let node = if fetch {
+            Some(network.get_node(current).await?)
+        } else {
+            network.get_local_node(current).await?
+        };
+        if let Some(node) = node {
+            queue.extend(node.parents);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Merge timelines, each newest first, into one feed of at most `limit` entries
+///
+/// Reposts and announcements carry no timestamp, they stay right below the newer post of their
+/// timeline.
+pub fn merge(timelines: Vec<Vec<TimelineEntry>>, limit: usize) -> Vec<TimelineEntry> {
+    merge_counted(timelines, limit).0
+}
+
+/// Like `merge`, also returning how many entries of each timeline made it into the feed
+///
+/// The entries of each timeline keep their order, so those in the feed are always the first ones.
+fn merge_counted(
+    timelines: Vec<Vec<TimelineEntry>>,
+    limit: usize,
+) -> (Vec<TimelineEntry>, Vec<usize>) {
+    // Entries before the first timestamp of their timeline count as newest
+    let mut timelines = timelines
+        .into_iter()
+        .map(|timeline| {
+            let mut newer = None;
+            timeline
+                .into_iter()
+                .map(move |entry| {
+                    let key = entry.timestamp().or(newer);
+                    newer = key;
+                    (key, entry)
+                })
+                .peekable()
+        })
+        .collect::<Vec<_>>();
+
+    let mut taken = vec![0; timelines.len()];
+    let mut feed = Vec::new();
+    while feed.len() < limit {
+        // The first of the newest, so that ties keep the order of the timelines
+        let newest = timelines
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(n, timeline)| timeline.peek().map(|(key, _)| (n, *key)))
+            .fold(None, |newest, (n, key)| match newest {
+                Some((_, newest_key)) if !is_newer(key, newest_key) => newest,
+                _ => Some((n, key)),
+            });
+
+        let Some((n, _)) = newest else {
+            break;
+        };
+        if let Some((_, entry)) = timelines[n].next() {
+            feed.push(entry);
+            taken[n] += 1;
+        }
+    }
+
+    (feed, taken)
+}
+
+fn is_newer(a: Option<time::OffsetDateTime>, b: Option<time::OffsetDateTime>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a > b,
+        (None, Some(_)) => true,
+        (_, None) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original(author: &str, id: &str, timestamp: i64) -> TimelineEntry {
+        TimelineEntry {
+            author: author.to_string(),
+            node_id: id.to_string(),
+            post_id: id.to_string(),
+            post: PostView::Original {
+                content_id: id.to_string(),
+                content_mime: mime::TEXT_PLAIN_UTF_8.to_string(),
+                timestamp: time::OffsetDateTime::from_unix_timestamp(timestamp).unwrap(),
+                text: None,
+            },
+        }
+    }
+
+    fn repost(author: &str, id: &str) -> TimelineEntry {
+        TimelineEntry {
+            author: author.to_string(),
+            node_id: id.to_string(),
+            post_id: id.to_string(),
+            post: PostView::Repost {
+                node_id: "other".to_string(),
+                post_id: "other".to_string(),
+                author: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_merge_orders_by_timestamp() {
+        let alice = vec![original("alice", "a2", 20), original("alice", "a1", 10)];
+        let bob = vec![
+            original("bob", "b3", 30),
+            repost("bob", "b2"),
+            original("bob", "b1", 5),
+        ];
+
+        let feed = merge(vec![alice, bob], 10);
+        let ids = feed.iter().map(|e| e.node_id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["b3", "b2", "a2", "a1", "b1"]);
+
+        assert_eq!(merge(vec![feed], 2).len(), 2);
+    }
+
+    /// The chain `a <- b <- c`, and `d` which diverged from it after `a`
+    async fn histories(network: &Network) -> [cid::Cid; 4] {
+        let node = |version, parents| distrox_types::node::Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(version),
+            parents,
+            post: None,
+        };
+
+        let a = network.insert_node(node(0, vec![])).await.unwrap();
+        let b = network.insert_node(node(0, vec![a])).await.unwrap();
+        let c = network.insert_node(node(0, vec![b])).await.unwrap();
+        let d = network.insert_node(node(1, vec![a])).await.unwrap();
+        [a, b, c, d]
+    }
+
+    async fn network() -> Network {
+        let _ = env_logger::try_init();
+        let listening_addr =
+            crate::network::ListeningAddrs(vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]);
+        Network::inmemory(listening_addr).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_contains_fast_forward() {
+        let network = network().await;
+        let [a, b, c, _] = histories(&network).await;
+
+        assert!(contains(&network, c, b, true).await.unwrap());
+        assert!(contains(&network, c, a, false).await.unwrap());
+        assert!(!contains(&network, b, c, false).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_contains_stale_head() {
+        let network = network().await;
+        let [a, b, c, _] = histories(&network).await;
+
+        // A republished older head is already part of our history
+        assert!(contains(&network, c, a, false).await.unwrap());
+        assert!(contains(&network, c, b, false).await.unwrap());
+        assert!(contains(&network, c, c, false).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_contains_divergent_head() {
+        let network = network().await;
+        let [a, _, c, d] = histories(&network).await;
+
+        assert!(!contains(&network, c, d, false).await.unwrap());
+        assert!(!contains(&network, d, c, true).await.unwrap());
+        assert!(contains(&network, d, a, true).await.unwrap());
+    }
+
+    /// Append a node with `post` to the timeline at the heads `parents`
+    async fn append(
+        network: &Network,
+        parents: Vec<cid::Cid>,
+        post: distrox_types::post::Post,
+    ) -> cid::Cid {
+        let post = network.insert_post(post).await.unwrap();
+        let node = distrox_types::node::Node {
+            protocol_version: distrox_types::protocol::ProtocolVersion(0),
+            parents,
+            post: Some(post),
+        };
+        network.insert_node(node).await.unwrap()
+    }
+
+    fn original_post(content: cid::Cid, timestamp: i64) -> distrox_types::post::Post {
+        distrox_types::post::Post::Original(distrox_types::post::OriginalPost {
+            content,
+            content_mime: distrox_types::util::Mime(mime::APPLICATION_OCTET_STREAM),
+            timestamp: distrox_types::util::OffsetDateTime(
+                time::OffsetDateTime::from_unix_timestamp(timestamp).unwrap(),
+            ),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_page_continues_at_cursor() {
+        let network = network().await;
+        let content = network
+            .insert_blob(futures::stream::iter(vec![0u8]))
+            .await
+            .unwrap();
+
+        let mut alice = None;
+        for timestamp in [10, 30, 50] {
+            let parents = alice.into_iter().collect();
+            alice = Some(append(&network, parents, original_post(content, timestamp)).await);
+        }
+        let mut bob = None;
+        for timestamp in [20, 40] {
+            let parents = bob.into_iter().collect();
+            bob = Some(append(&network, parents, original_post(content, timestamp)).await);
+        }
+        let heads = [
+            ("alice".to_string(), alice.unwrap()),
+            ("bob".to_string(), bob.unwrap()),
+        ];
+
+        let mut cursor = TimelineCursor::from_heads(&heads);
+        let mut authors = Vec::new();
+        loop {
+            let (entries, next) = page(&network, &heads, &cursor, 2).await.unwrap();
+            authors.extend(entries.into_iter().map(|e| e.author));
+            match next {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+        assert_eq!(authors, ["alice", "bob", "alice", "bob", "alice"]);
+    }
+
+    #[tokio::test]
+    async fn test_page_merged_history_has_no_repeats() {
+        let network = network().await;
+        let content = network
+            .insert_blob(futures::stream::iter(vec![0u8]))
+            .await
+            .unwrap();
+
+        // `a` merges `b` and `c`, and `c` is also a parent of `b`
+        let d = append(&network, vec![], original_post(content, 10)).await;
+        let c = append(&network, vec![d], original_post(content, 20)).await;
+        let b = append(&network, vec![c], original_post(content, 30)).await;
+        let a = append(&network, vec![b, c], original_post(content, 40)).await;
+        let heads = [("alice".to_string(), a)];
+
+        for limit in [1, 3] {
+            let mut cursor = TimelineCursor::from_heads(&heads);
+            let mut shown = Vec::new();
+            loop {
+                let (entries, next) = page(&network, &heads, &cursor, limit).await.unwrap();
+                shown.extend(entries.into_iter().map(|e| e.node_id));
+                match next {
+                    Some(next) => cursor = next,
+                    None => break,
+                }
+            }
+
+            let expected = [a, b, c, d].map(|cid| cid.to_string());
+            assert_eq!(shown, expected, "limit {limit}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_page_resolves_repost_author() {
+        let network = network().await;
+        let content = network
+            .insert_blob(futures::stream::iter(vec![0u8]))
+            .await
+            .unwrap();
+
+        let original = original_post(content, 10);
+        let post_id = network
+            .insert_post(original_post(content, 10))
+            .await
+            .unwrap();
+        let alice = append(&network, vec![], original).await;
+        let repost = distrox_types::post::Post::Repost(distrox_types::post::Repost {
+            node_id: alice,
+            post_id,
+        });
+        let bob = append(&network, vec![], repost).await;
+
+        let heads = [("alice".to_string(), alice), ("bob".to_string(), bob)];
+        let (entries, next) = page(&network, &heads, &TimelineCursor::from_heads(&heads), 10)
+            .await
+            .unwrap();
+        assert!(next.is_none());
+
+        let reposted_from = entries.iter().find_map(|e| match &e.post {
+            PostView::Repost { author, .. } => Some(author.clone()),
+            _ => None,
+        });
+        assert_eq!(reposted_from, Some(Some("alice".to_string())));
+    }
+
+    #[test]
+    fn test_entry_json_is_stable() {
+        let json = serde_json::to_value(original("alice", "a1", 0)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "author": "alice",
+                "node_id": "a1",
+                "post_id": "a1",
+                "post": {
+                    "kind": "original",
+                    "content_id": "a1",
+                    "content_mime": "text/plain; charset=utf-8",
+                    "timestamp": "1970-01-01T00:00:00Z",
+                    "text": null,
+                },
+            })
+        );
+    }
+}
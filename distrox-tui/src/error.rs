@@ -0,0 +1,5 @@
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to use the terminal")]
+    Terminal(#[source] std::io::Error),
+}
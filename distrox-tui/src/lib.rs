@@ -0,0 +1,139 @@
+pub mod error;
+mod state;
+mod ui;
+
+use crossterm::event::EventStream;
+use crossterm::event::KeyEventKind;
+use distrox_lib::command::Command;
+use distrox_lib::command::CommandOutput;
+use distrox_lib::command::CommandSender;
+use distrox_lib::event::EventReceiver;
+use futures::StreamExt;
+use ratatui::backend::CrosstermBackend;
+use ratatui::Terminal;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::error::Error;
+use crate::state::Action;
+use crate::state::State;
+use crate::state::Update;
+
+/// How many posts of the timeline are shown
+const TIMELINE_LIMIT: usize = 200;
+
+/// How often the timeline and peers are reloaded
+const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Run the terminal interface until the user quits, then ask the backend to quit as well
+pub async fn start(sender: CommandSender, mut events: EventReceiver) -> Result<(), Error> {
+    let mut terminal = TerminalGuard::enter()?;
+    let mut state = State::default();
+    let mut input = EventStream::new();
+    let (updates_sender, mut updates) = tokio::sync::mpsc::unbounded_channel();
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        terminal
+            .0
+            .draw(|frame| ui::draw(frame, &state))
+            .map_err(Error::Terminal)?;
+
+        let action = tokio::select! {
+            input = input.next() => match input {
+                Some(Ok(crossterm::event::Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    state.handle_key(key)
+                }
+                Some(Ok(_)) => None,
+                Some(Err(error)) => return Err(Error::Terminal(error)),
+                None => Some(Action::Quit),
+            },
+
+            event = events.recv() => match event {
+                Ok(event) => state.handle_event(&event),
+                Err(RecvError::Lagged(_)) => None,
+                Err(RecvError::Closed) => return Ok(()),
+            },
+
+            Some(update) = updates.recv() => {
+                state.apply(update);
+                None
+            }
+
+            _ = refresh.tick() => {
+                request(&sender, Action::RefreshPeers, updates_sender.clone());
+                Some(Action::RefreshTimeline)
+            }
+        };
+
+        match action {
+            Some(Action::Quit) => {
+                let _ = sender.send(Command::QuitApp).await;
+                return Ok(());
+            }
+            Some(action) => request(&sender, action, updates_sender.clone()),
+            None => {}
+        }
+    }
+}
+
+/// Let the backend do `action` without blocking the interface, the result comes back as update
+fn request(sender: &CommandSender, action: Action, updates: UnboundedSender<Update>) {
+    let sender = sender.clone();
+    tokio::spawn(async move {
+        let (command, refresh) = match action {
+            Action::Quit => return,
+            Action::Post(text) => (Command::PostText { text }, Some(timeline_command())),
+            Action::Connect(uri) => (Command::ConnectTo { uri }, Some(Command::Peers)),
+            Action::RefreshTimeline => (timeline_command(), None),
+            Action::RefreshPeers => (Command::Peers, None),
+        };
+
+        for command in std::iter::once(command).chain(refresh) {
+            let update = match sender.request(command).await {
+                Ok(CommandOutput::Timeline { entries, .. }) => Update::Timeline(entries),
+                Ok(CommandOutput::Peers { peers }) => Update::Peers(peers),
+                Ok(CommandOutput::Node { node_id }) => Update::Status(format!("Posted {node_id}")),
+                Ok(_) => Update::Status("Done".to_string()),
+                Err(error) => Update::Status(format!("Error: {error}")),
+            };
+
+            if updates.send(update).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+fn timeline_command() -> Command {
+    Command::Timeline {
+        limit: TIMELINE_LIMIT,
+        after: None,
+    }
+}
+
+/// Puts the terminal back into its normal mode when the interface ends, also on errors
+struct TerminalGuard(Terminal<CrosstermBackend<std::io::Stdout>>);
+
+impl TerminalGuard {
+    fn enter() -> Result<Self, Error> {
+        crossterm::terminal::enable_raw_mode().map_err(Error::Terminal)?;
+        crossterm::execute!(std::io::stdout(), crossterm::terminal::EnterAlternateScreen)
+            .map_err(Error::Terminal)?;
+
+        Terminal::new(CrosstermBackend::new(std::io::stdout()))
+            .map(TerminalGuard)
+            .map_err(Error::Terminal)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+        let _ = crossterm::execute!(
+            self.0.backend_mut(),
+            crossterm::terminal::LeaveAlternateScreen
+        );
+        let _ = self.0.show_cursor();
+    }
+}
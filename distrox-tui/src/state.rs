@@ -0,0 +1,219 @@
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyModifiers;
+use distrox_lib::command::Peer;
+use distrox_lib::event::Event;
+use distrox_lib::metrics::NetworkMetrics;
+use distrox_lib::timeline::TimelineEntry;
+
+/// How many timeline entries a page up or down moves
+const PAGE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Focus {
+    Compose,
+    Timeline,
+}
+
+/// What the user asked for, to be done by the backend
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Action {
+    Quit,
+    Post(String),
+    Connect(String),
+    RefreshTimeline,
+    RefreshPeers,
+}
+
+/// Results of the backend, fed back into the state
+#[derive(Debug)]
+pub(crate) enum Update {
+    Timeline(Vec<TimelineEntry>),
+    Peers(Vec<Peer>),
+    Status(String),
+}
+
+pub(crate) struct State {
+    pub(crate) timeline: Vec<TimelineEntry>,
+    pub(crate) selected: usize,
+    pub(crate) peers: Vec<Peer>,
+    pub(crate) metrics: Option<NetworkMetrics>,
+    pub(crate) compose: String,
+    pub(crate) focus: Focus,
+    pub(crate) status: Option<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            timeline: Vec::new(),
+            selected: 0,
+            peers: Vec::new(),
+            metrics: None,
+            compose: String::new(),
+            focus: Focus::Compose,
+            status: None,
+        }
+    }
+}
+
+impl State {
+    pub(crate) fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.modifiers.contains(KeyModifiers::CONTROL)
+            && matches!(key.code, KeyCode::Char('c') | KeyCode::Char('q'))
+        {
+            return Some(Action::Quit);
+        }
+
+        if key.code == KeyCode::Tab {
+            self.focus = match self.focus {
+                Focus::Compose => Focus::Timeline,
+                Focus::Timeline => Focus::Compose,
+            };
+            return None;
+        }
+
+        match self.focus {
+            Focus::Compose => self.handle_compose_key(key),
+            Focus::Timeline => self.handle_timeline_key(key),
+        }
+    }
+
+    fn handle_compose_key(&mut self, key: KeyEvent) -> Option<Action> {
+        match key.code {
+            KeyCode::Char(c) => self.compose.push(c),
+            KeyCode::Backspace => {
+                self.compose.pop();
+            }
+            KeyCode::Esc => self.compose.clear(),
+            KeyCode::Enter => {
+                let text = std::mem::take(&mut self.compose);
+                let text = text.trim();
+                if let Some(addr) = text.strip_prefix("/connect ") {
+                    return Some(Action::Connect(addr.trim().to_string()));
+                }
+                if !text.is_empty() {
+                    return Some(Action::Post(text.to_string()));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_timeline_key(&mut self, key: KeyEvent) -> Option<Action> {
+        let last = self.timeline.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Char('q') => return Some(Action::Quit),
+            KeyCode::Char('r') => return Some(Action::RefreshTimeline),
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.selected = (self.selected + 1).min(last),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(PAGE),
+            KeyCode::PageDown => self.selected = (self.selected + PAGE).min(last),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = last,
+            _ => {}
+        }
+        None
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &Event) -> Option<Action> {
+        match event {
+            Event::ConnectionEstablished { .. } | Event::ConnectionClosed { .. } => {
+                Some(Action::RefreshPeers)
+            }
+            Event::TimelineChanged { .. } => Some(Action::RefreshTimeline),
+            Event::Metrics(metrics) => {
+                self.metrics = Some((**metrics).clone());
+                None
+            }
+            Event::Discovered(discovery) => {
+                self.status = Some(format!(
+                    "{} announced a post of {}",
+                    discovery.announced_by, discovery.author
+                ));
+                None
+            }
+            Event::ConfigReloaded(changes) => {
+                self.status = Some(if changes.restart_required.is_empty() {
+                    "Configuration reloaded".to_string()
+                } else {
+                    format!(
+                        "Configuration reloaded, restart to apply {}",
+                        changes.restart_required.join(", ")
+                    )
+                });
+                None
+            }
+            Event::PubSubSubscribe(_) | Event::PubSubUnsubscribe(_) => None,
+        }
+    }
+
+    pub(crate) fn apply(&mut self, update: Update) {
+        match update {
+            Update::Timeline(timeline) => {
+                // Stay at the same post if it is still there, new posts come in on top
+                let selected = self.timeline.get(self.selected).map(|e| e.node_id.clone());
+                self.timeline = timeline;
+                self.selected = selected
+                    .and_then(|id| self.timeline.iter().position(|e| e.node_id == id))
+                    .unwrap_or(0);
+            }
+            Update::Peers(peers) => self.peers = peers,
+            Update::Status(status) => self.status = Some(status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn type_text(state: &mut State, text: &str) {
+        for c in text.chars() {
+            assert_eq!(state.handle_key(key(KeyCode::Char(c))), None);
+        }
+    }
+
+    #[test]
+    fn test_compose() {
+        let mut state = State::default();
+        type_text(&mut state, "Hello!");
+        state.handle_key(key(KeyCode::Backspace));
+        assert_eq!(
+            state.handle_key(key(KeyCode::Enter)),
+            Some(Action::Post("Hello".to_string()))
+        );
+        assert!(state.compose.is_empty());
+
+        assert_eq!(state.handle_key(key(KeyCode::Enter)), None);
+
+        type_text(&mut state, "/connect /ip4/127.0.0.1/tcp/4001");
+        assert_eq!(
+            state.handle_key(key(KeyCode::Enter)),
+            Some(Action::Connect("/ip4/127.0.0.1/tcp/4001".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_focus_switches_keys() {
+        let mut state = State::default();
+        type_text(&mut state, "q");
+        assert_eq!(state.compose, "q");
+
+        state.handle_key(key(KeyCode::Tab));
+        assert_eq!(state.focus, Focus::Timeline);
+        assert_eq!(
+            state.handle_key(key(KeyCode::Char('q'))),
+            Some(Action::Quit)
+        );
+
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        state.handle_key(key(KeyCode::Tab));
+        assert_eq!(state.handle_key(ctrl_c), Some(Action::Quit));
+    }
+}
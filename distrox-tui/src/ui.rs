@@ -0,0 +1,179 @@
+use distrox_lib::timeline::PostView;
+use distrox_lib::timeline::TimelineEntry;
+use ratatui::layout::Constraint;
+use ratatui::layout::Direction;
+use ratatui::layout::Layout;
+use ratatui::layout::Rect;
+use ratatui::style::Modifier;
+use ratatui::style::Style;
+use ratatui::text::Line;
+use ratatui::text::Span;
+use ratatui::widgets::Block;
+use ratatui::widgets::Borders;
+use ratatui::widgets::List;
+use ratatui::widgets::ListItem;
+use ratatui::widgets::ListState;
+use ratatui::widgets::Paragraph;
+use ratatui::Frame;
+
+use crate::state::Focus;
+use crate::state::State;
+
+pub(crate) fn draw(frame: &mut Frame, state: &State) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(5),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .split(frame.size());
+
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
+        .split(rows[0]);
+
+    let sidebar = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(5), Constraint::Min(3)])
+        .split(columns[1]);
+
+    draw_timeline(frame, state, columns[0]);
+    draw_connection(frame, state, sidebar[0]);
+    draw_peers(frame, state, sidebar[1]);
+    draw_compose(frame, state, rows[1]);
+
+    let status = state.status.as_deref().unwrap_or(
+        "Tab: switch focus  Up/Down: scroll  r: refresh  /connect <multiaddr>  Ctrl-C: quit",
+    );
+    frame.render_widget(Paragraph::new(status), rows[2]);
+}
+
+fn block(title: &str, focused: bool) -> Block<'_> {
+    let block = Block::default().borders(Borders::ALL).title(title);
+    if focused {
+        block.border_style(Style::default().add_modifier(Modifier::BOLD))
+    } else {
+        block
+    }
+}
+
+fn draw_timeline(frame: &mut Frame, state: &State, area: Rect) {
+    let items = state.timeline.iter().map(timeline_item).collect::<Vec<_>>();
+
+    let list = List::new(items)
+        .block(block("Timeline", state.focus == Focus::Timeline))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+    let mut list_state = ListState::default();
+    if state.focus == Focus::Timeline && !state.timeline.is_empty() {
+        list_state.select(Some(state.selected));
+    }
+    *list_state.offset_mut() = state.selected;
+    frame.render_stateful_widget(list, area, &mut list_state);
+}
+
+fn timeline_item(entry: &TimelineEntry) -> ListItem<'_> {
+    let author = Span::styled(
+        short_id(&entry.author),
+        Style::default().add_modifier(Modifier::BOLD),
+    );
+
+    let mut lines = match &entry.post {
+        PostView::Original {
+            content_mime,
+            timestamp,
+            text,
+            ..
+        } => {
+            let timestamp = timestamp
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_else(|_| timestamp.to_string());
+            let header = Line::from(vec![author, Span::raw(format!("  {timestamp}"))]);
+
+            let body = match text {
+                Some(text) => text.lines().map(|l| Line::from(l.to_string())).collect(),
+                None => vec![Line::from(format!("[{content_mime}]"))],
+            };
+            std::iter::once(header).chain(body).collect::<Vec<_>>()
+        }
+        PostView::Repost {
+            node_id,
+            author: original,
+            ..
+        } => vec![Line::from(vec![
+            author,
+            Span::raw(format!("  reposted {}", shared(node_id, original))),
+        ])],
+        PostView::Announce {
+            node_id,
+            author: original,
+            ..
+        } => vec![Line::from(vec![
+            author,
+            Span::raw(format!("  announced {}", shared(node_id, original))),
+        ])],
+    };
+    lines.push(Line::from(""));
+    ListItem::new(lines)
+}
+
+/// What a repost or announcement refers to, the post of its author if we know them
+fn shared(node_id: &str, author: &Option<String>) -> String {
+    match author {
+        Some(author) => format!("a post of {}", short_id(author)),
+        None => short_id(node_id).to_string(),
+    }
+}
+
+fn draw_connection(frame: &mut Frame, state: &State, area: Rect) {
+    let lines = match state.metrics.as_ref() {
+        Some(metrics) => vec![
+            Line::from(format!("Connections: {}", metrics.connection_count())),
+            Line::from(format!("In:  {} bytes", metrics.bytes_in)),
+            Line::from(format!("Out: {} bytes", metrics.bytes_out)),
+        ],
+        None => vec![Line::from(format!("Peers: {}", state.peers.len()))],
+    };
+
+    frame.render_widget(
+        Paragraph::new(lines).block(block("Connection", false)),
+        area,
+    );
+}
+
+fn draw_peers(frame: &mut Frame, state: &State, area: Rect) {
+    let items = state
+        .peers
+        .iter()
+        .map(|peer| ListItem::new(peer.peer_id.as_str()))
+        .collect::<Vec<_>>();
+
+    frame.render_widget(List::new(items).block(block("Peers", false)), area);
+}
+
+fn draw_compose(frame: &mut Frame, state: &State, area: Rect) {
+    let focused = state.focus == Focus::Compose;
+    frame.render_widget(
+        Paragraph::new(state.compose.as_str()).block(block("Compose", focused)),
+        area,
+    );
+
+    if focused {
+        let width = state.compose.chars().count() as u16;
+        frame.set_cursor(
+            (area.x + 1 + width).min(area.right().saturating_sub(2)),
+            area.y + 1,
+        );
+    }
+}
+
+/// Peer ids all start alike, their end tells them apart
+fn short_id(id: &str) -> String {
+    let start = id.len().saturating_sub(8);
+    match id.get(start..) {
+        Some(end) if start > 0 => format!("…{end}"),
+        _ => id.to_string(),
+    }
+}
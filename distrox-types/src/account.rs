@@ -0,0 +1,39 @@
+use libipld::DagCbor;
+
+use crate::id::AccountRecordId;
+use crate::id::NodeId;
+use crate::protocol::ProtocolVersion;
+
+/// An identity that can post into one timeline from several devices
+///
+/// The account is identified by its public key, the record itself changes whenever a device gets
+/// added.
+#[derive(Clone, Eq, PartialEq, Debug, DagCbor)]
+pub struct Account {
+    pub protocol_version: ProtocolVersion,
+
+    /// Protobuf encoded public key of the account
+    pub public_key: Vec<u8>,
+
+    pub devices: Vec<DeviceDelegation>,
+}
+
+/// The right of a device to post on behalf of an account
+#[derive(Clone, Eq, PartialEq, Debug, DagCbor)]
+pub struct DeviceDelegation {
+    /// The PeerId of the device, as bytes
+    pub device: Vec<u8>,
+
+    /// Signature of `device` made with the account key
+    pub signature: Vec<u8>,
+}
+
+/// Messages the devices of one account exchange to keep their timelines in sync
+#[derive(Debug, DagCbor)]
+pub enum AccountMessage {
+    /// The latest node of the sending device
+    Head(NodeId),
+
+    /// A new version of the account record
+    Account(AccountRecordId),
+}
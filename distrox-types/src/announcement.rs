@@ -0,0 +1,16 @@
+use libipld::DagCbor;
+
+use crate::id::NodeId;
+use crate::id::PostId;
+
+/// Sent on an announcement topic to tell the network about a post that might be nice to know
+///
+/// The announcing account also puts a `post::Announce` into its own timeline.
+#[derive(Debug, DagCbor)]
+pub struct Announcement {
+    /// The PeerId of the account that wrote the announced post, as bytes
+    pub author: Vec<u8>,
+
+    pub node_id: NodeId,
+    pub post_id: PostId,
+}
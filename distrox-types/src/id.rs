@@ -1,3 +1,4 @@
 pub type NodeId = libipld::Cid;
 pub type PostId = libipld::Cid;
 pub type ContentId = libipld::Cid;
+pub type AccountRecordId = libipld::Cid;
@@ -1,3 +1,5 @@
+pub mod account;
+pub mod announcement;
 pub mod id;
 pub mod node;
 pub mod post;